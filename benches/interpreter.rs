@@ -0,0 +1,55 @@
+//! runs the roms in benches/roms headless, and reports how many instructions a second each one
+//! gets through. `cargo bench` builds and runs it, and `cargo bench -- NAME` runs only the roms
+//! with NAME in their name
+//!
+//! every rom gets a deterministic system device, so nothing sleeps or reads the terminal, and an
+//! empty drive in slot 1. each one runs twice, once with the block cache
+
+use std::time::Instant;
+use avc2::{asm, DevSpec, Processor, Status, System};
+
+const ROMS: &[&str] = &[
+    "arith", // a tight arithmetic loop
+    "calls", // JSR2 and JMP2r
+    "copy", // LDA2 and STA2
+    "drive", // drive dma
+];
+
+fn main() {
+    // cargo passes --bench
+    let filter: Vec<String> = std::env::args().skip(1).filter(|a| !a.starts_with("--")).collect();
+    for name in ROMS {
+        if !filter.is_empty() && !filter.iter().any(|f| name.contains(f.as_str())) {
+            continue
+        }
+        let path = format!("{}/benches/roms/{}.avc", env!("CARGO_MANIFEST_DIR"), name);
+        let rom = asm::assemble_file(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        bench(name, &rom, false);
+        bench(&format!("{}/blocks", name), &rom, true)
+    }
+}
+
+fn bench(name: &str, rom: &[u8], blocks: bool) {
+    let drive = std::env::temp_dir().join(format!("avc2-bench-{}.avd", std::process::id()));
+    std::fs::write(&drive, b"AVD\0").unwrap();
+    let devs = vec![DevSpec::new(1, 2, drive.to_str().unwrap())];
+    let mut p = Processor::new(asm::strip_header(rom).unwrap(), devs).unwrap();
+    p.attach_device(0, Box::new(System::deterministic(0))).unwrap();
+    p.set_block_cache(blocks);
+
+    let start = Instant::now();
+    let mut count: u64 = 0;
+    let status = loop {
+        let (status, ran) = p.execute_block();
+        count += ran as u64;
+        if status != Status::Running {
+            break status
+        }
+    };
+    let secs = start.elapsed().as_secs_f64();
+    p.shutdown();
+    std::fs::remove_file(&drive).unwrap();
+
+    assert_eq!(status, Status::Halted(0), "{}", name);
+    println!("{:<12} {:>11} instructions in {:.3}s, {:.1} MIPS", name, count, secs, count as f64 / secs / 1e6)
+}
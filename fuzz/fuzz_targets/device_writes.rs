@@ -0,0 +1,45 @@
+//! a rom running with a drive and a timer attached, with writes to the device page from outside
+//! the program between its instructions
+//!
+//! the input is a 2 byte big-endian rom length, the rom, then 3 byte records: how many
+//! instructions to run, a port on the device page, and the value to write to it
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use avc2::{DevSpec, Processor, Status, System};
+
+/// how long the rom runs for after the last write
+const STEPS: usize = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return
+    }
+    let len = (u16::from_be_bytes([data[0], data[1]]) as usize).min(data.len() - 2);
+    let (rom, writes) = data[2..].split_at(len);
+
+    // a fresh, empty drive for every input
+    let drive = std::env::temp_dir().join(format!("avc2-fuzz-{}.avd", std::process::id()));
+    std::fs::write(&drive, b"AVD\0").unwrap();
+    let devs = vec![DevSpec::new(1, 2, drive.to_str().unwrap()), DevSpec::new(2, 3, "")];
+    let mut p = match Processor::new(rom, devs) {
+        Ok(p) => p,
+        Err(_) => return
+    };
+    p.attach_device(0, Box::new(System::deterministic(0))).unwrap();
+
+    for w in writes.chunks_exact(3) {
+        if !run(&mut p, w[0] as usize) {
+            return
+        }
+        // a fault (like dma into the device page) is fine, as long as it's returned
+        let _ = p.mem_mut().set(0xff00 | w[1] as u16, w[2]);
+    }
+    run(&mut p, STEPS);
+});
+
+/// false once the processor stops
+fn run(p: &mut Processor, steps: usize) -> bool {
+    (0..steps).all(|_| p.execute_once() == Status::Running)
+}
@@ -0,0 +1,27 @@
+//! arbitrary rom files, header and all, run for a bounded number of instructions
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use avc2::{asm, Processor, Status, System};
+
+/// enough to get through some loops, without making every input slow
+const STEPS: usize = 10_000;
+
+fuzz_target!(|file: &[u8]| {
+    let code = match asm::strip_header(file) {
+        Some(code) => code,
+        None => return
+    };
+    let mut p = match Processor::new(code, Vec::new()) {
+        Ok(p) => p,
+        Err(_) => return // too large to load
+    };
+    // no sleeping on WAIT, and no terminal
+    p.attach_device(0, Box::new(System::deterministic(0))).unwrap();
+    for _ in 0..STEPS {
+        if p.execute_once() != Status::Running {
+            break
+        }
+    }
+});
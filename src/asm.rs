@@ -0,0 +1,878 @@
+//! an assembler for `.avc` source files
+//!
+//! a source file is a list of instructions, separated by whitespace. an instruction is a
+//! mnemonic from opcode_table.txt, a raw byte written `.x(hh)`, a label definition (`name:`),
+//! a macro, or one of the directives below. `//` starts a comment that runs to the end of the line
+//!
+//! - `.b(expr)` and `.w(expr)` assemble an expression as a byte or a big endian word
+//! - `.r(expr)` assembles the offset from the byte after it to an address, for the relative
+//!   instruction that follows it, like `LIT .r(loop) JMP`
+//! - `.def(NAME, expr)` defines a constant
+//! - `.str("text")` assembles a string, and `.fat("text")` assembles its length as a word first
+//! - `.bin("path")` assembles the contents of a file
+//! - `.include("path")` assembles another source file in place
+//! - `.macro(NAME, params...)` on a line of its own starts a macro, which runs until a line
+//!   holding `.end`. `NAME(args...)` then assembles the body with the parameters replaced by
+//!   the arguments, and `@` replaced by a number unique to the expansion
+//!
+//! paths are relative to the file they're in.
+//! expressions are made of numbers (decimal, `0x` hex or `0b` binary), labels, constants,
+//! brackets and the operators `+ - * / % & | ^ << >> ~`, with the usual precedence
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::fs;
+use crate::opcodes;
+use crate::utils::Avc2Error;
+use crate::memory::{MAX_ROM_SIZE, ROM_START};
+use crate::symbols::Symbols;
+
+/// the first 4 bytes of every rom file
+pub const ROM_HEADER: [u8; 4] = [0x41, 0x56, 0x43, 0x00];
+
+/// the code in a rom file, or None if it doesn't start with the header
+pub fn strip_header(file: &[u8]) -> Option<&[u8]> {
+    file.strip_prefix(&ROM_HEADER)
+}
+
+/// how deep constants, includes and macros can nest, so loops get caught
+const MAX_DEPTH: usize = 64;
+
+/// assemble source code into a rom, header included. paths in the source are relative to the
+/// current directory
+pub fn assemble(src: &str) -> Result<Vec<u8>, Avc2Error> {
+    let mut asm = Assembler::new();
+    asm.source(src, None, PathBuf::new())?;
+    asm.rom()
+}
+
+/// assemble a source file into a rom, header included
+pub fn assemble_file(path: impl AsRef<Path>) -> Result<Vec<u8>, Avc2Error> {
+    assemble_file_with_symbols(path).map(|(rom, _)| rom)
+}
+
+/// assemble a source file into a rom, and the symbols for it
+pub fn assemble_file_with_symbols(path: impl AsRef<Path>) -> Result<(Vec<u8>, Symbols), Avc2Error> {
+    let mut asm = Assembler::new();
+    asm.file(path.as_ref())?;
+    Ok((asm.rom()?, asm.symbols()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Width {
+    Byte,
+    Word,
+    Relative
+}
+
+#[derive(Debug)]
+enum Item {
+    Bytes(Vec<u8>),
+    /// an expression, assembled once all the labels are known
+    Value(Width, Expr)
+}
+impl Item {
+    fn len(&self) -> usize {
+        match self {
+            Item::Bytes(b) => b.len(),
+            Item::Value(Width::Word, _) => 2,
+            Item::Value(..) => 1
+        }
+    }
+}
+
+enum Symbol {
+    Label(u16),
+    Const(Expr)
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>
+}
+
+/// where something came from: an index into `Assembler::files`, and a line number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Loc {
+    file: usize,
+    line: usize
+}
+
+struct Assembler {
+    mnemonics: HashMap<String, u8>,
+    /// each item, with where it came from
+    items: Vec<(Item, Loc)>,
+    symbols: HashMap<String, Symbol>,
+    macros: HashMap<String, Macro>,
+    /// the macro being defined, if any
+    recording: Option<(String, Macro)>,
+    /// the name of each file, or None for source not from a file
+    files: Vec<Option<String>>,
+    /// the number of bytes assembled so far
+    size: usize,
+    loc: Loc,
+    /// the directory paths are relative to
+    dir: PathBuf,
+    depth: usize,
+    expansions: usize
+}
+
+impl Assembler {
+    fn new() -> Assembler {
+        Assembler {
+            mnemonics: mnemonic_table(),
+            items: Vec::new(),
+            symbols: HashMap::new(),
+            macros: HashMap::new(),
+            recording: None,
+            files: Vec::new(),
+            size: 0,
+            loc: Loc { file: 0, line: 0 },
+            dir: PathBuf::new(),
+            depth: 0,
+            expansions: 0
+        }
+    }
+
+    /// an error at the current location
+    fn error(&self, msg: impl std::fmt::Display) -> Avc2Error {
+        self.error_at(self.loc, msg)
+    }
+    fn error_at(&self, loc: Loc, msg: impl std::fmt::Display) -> Avc2Error {
+        match self.files.get(loc.file) {
+            Some(Some(name)) => Avc2Error::AsmError(format!("{}:{}: {}", name, loc.line, msg)),
+            _ => Avc2Error::AsmError(format!("line {}: {}", loc.line, msg))
+        }
+    }
+
+    fn file(&mut self, path: &Path) -> Result<(), Avc2Error> {
+        let src = fs::read_to_string(path).map_err(|e| self.error(format!("can't read {}: {}", path.display(), e)))?;
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.source(&src, Some(path.display().to_string()), dir)
+    }
+
+    fn source(&mut self, src: &str, name: Option<String>, dir: PathBuf) -> Result<(), Avc2Error> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("includes are nested too deeply"))
+        }
+        self.depth += 1;
+        self.files.push(name);
+        let file = self.files.len() - 1;
+        let (old_loc, old_dir) = (self.loc, std::mem::replace(&mut self.dir, dir));
+        for (i, line) in src.lines().enumerate() {
+            self.loc = Loc { file, line: i + 1 };
+            self.parse_line(line)?
+        }
+        if let Some((name, _)) = &self.recording {
+            return Err(self.error(format!("macro {} has no .end", name)))
+        }
+        self.loc = old_loc;
+        self.dir = old_dir;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), Avc2Error> {
+        let words = split_words(line).map_err(|e| self.error(e))?;
+        if let Some((_, m)) = &mut self.recording {
+            if words.first() == Some(&".end") {
+                if words.len() > 1 {
+                    return Err(self.error(".end must be on a line of its own"))
+                }
+                let (name, m) = self.recording.take().unwrap();
+                self.macros.insert(name, m);
+            }
+            else {
+                m.body.push(line.to_string())
+            }
+            return Ok(())
+        }
+        if words.first().is_some_and(|w| w.starts_with(".macro(")) {
+            if words.len() > 1 {
+                return Err(self.error(".macro must be on a line of its own"))
+            }
+            return self.start_macro(words[0])
+        }
+        for word in words {
+            self.parse_word(word)?
+        }
+        Ok(())
+    }
+
+    fn start_macro(&mut self, word: &str) -> Result<(), Avc2Error> {
+        let args = directive_args(word).ok_or_else(|| self.error(format!("bad directive {}", word)))?;
+        let mut params = split_args(args).into_iter();
+        let name = params.next().unwrap_or_default();
+        check_name(name).map_err(|e| self.error(e))?;
+        if self.macros.contains_key(name) || lookup(&self.mnemonics, name).is_some() {
+            return Err(self.error(format!("{} is already defined", name)))
+        }
+        let params: Vec<String> = params.map(String::from).collect();
+        for p in &params {
+            check_name(p).map_err(|e| self.error(e))?
+        }
+        self.recording = Some((name.to_string(), Macro { params, body: Vec::new() }));
+        Ok(())
+    }
+
+    fn parse_word(&mut self, word: &str) -> Result<(), Avc2Error> {
+        if let Some(label) = word.strip_suffix(':') {
+            check_name(label).map_err(|e| self.error(e))?;
+            let addr = (ROM_START + self.size) as u16;
+            return self.define(label, Symbol::Label(addr))
+        }
+        if let Some(directive) = word.strip_prefix('.') {
+            let name = directive.split('(').next().unwrap_or_default();
+            let args = directive_args(word).ok_or_else(|| self.error(format!("bad directive {}", word)))?;
+            let item = match name {
+                "x" => {
+                    let b = u8::from_str_radix(args, 16).map_err(|_| self.error(format!("bad byte {}", word)))?;
+                    Item::Bytes(vec![b])
+                }
+                "b" => Item::Value(Width::Byte, self.expr(args)?),
+                "w" => Item::Value(Width::Word, self.expr(args)?),
+                "r" => Item::Value(Width::Relative, self.expr(args)?),
+                "def" => {
+                    let (name, expr) = args.split_once(',').ok_or_else(|| self.error("usage: .def(NAME, expr)"))?;
+                    let name = name.trim();
+                    check_name(name).map_err(|e| self.error(e))?;
+                    let expr = self.expr(expr)?;
+                    return self.define(name, Symbol::Const(expr))
+                }
+                "str" | "fat" => {
+                    let s = parse_string(args).map_err(|e| self.error(e))?;
+                    let mut bytes = Vec::new();
+                    if name == "fat" {
+                        let len = u16::try_from(s.len()).map_err(|_| self.error("the string is too long"))?;
+                        bytes.extend(len.to_be_bytes())
+                    }
+                    bytes.extend(s);
+                    Item::Bytes(bytes)
+                }
+                "bin" => {
+                    let path = self.path(args)?;
+                    Item::Bytes(fs::read(&path).map_err(|e| self.error(format!("can't read {}: {}", path.display(), e)))?)
+                }
+                "include" => {
+                    let path = self.path(args)?;
+                    return self.file(&path)
+                }
+                "end" => return Err(self.error(".end without .macro")),
+                _ => return Err(self.error(format!("unknown directive .{}", name)))
+            };
+            self.push(item);
+            return Ok(())
+        }
+        let name = word.split('(').next().unwrap_or_default();
+        if self.macros.contains_key(name) {
+            return self.expand(name, word)
+        }
+        let instr = lookup(&self.mnemonics, word).ok_or_else(|| self.error(format!("unknown instruction {}", word)))?;
+        self.push(Item::Bytes(vec![instr]));
+        Ok(())
+    }
+
+    fn expand(&mut self, name: &str, word: &str) -> Result<(), Avc2Error> {
+        let args = if word.len() > name.len() {
+            let args = word[name.len()..].strip_prefix('(').and_then(|a| a.strip_suffix(')'))
+                .ok_or_else(|| self.error(format!("bad macro call {}", word)))?;
+            split_args(args)
+        }
+        else {
+            Vec::new()
+        };
+        let m = &self.macros[name];
+        if args.len() != m.params.len() {
+            return Err(self.error(format!("macro {} takes {} arguments, not {}", name, m.params.len(), args.len())))
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("macros are nested too deeply"))
+        }
+        self.expansions += 1;
+        let body: Vec<String> = m.body.iter().map(|l| substitute(l, &m.params, &args, self.expansions)).collect();
+        // the expansion counts as being on the line of the call
+        self.depth += 1;
+        for line in body {
+            self.parse_line(&line)?
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn expr(&self, s: &str) -> Result<Expr, Avc2Error> {
+        Expr::parse(s).map_err(|e| self.error(e))
+    }
+    fn path(&self, arg: &str) -> Result<PathBuf, Avc2Error> {
+        let s = parse_string(arg).map_err(|e| self.error(e))?;
+        let s = String::from_utf8(s).map_err(|_| self.error("bad path"))?;
+        Ok(self.dir.join(s))
+    }
+
+    fn push(&mut self, item: Item) {
+        self.size += item.len();
+        self.items.push((item, self.loc))
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol) -> Result<(), Avc2Error> {
+        if self.symbols.contains_key(name) {
+            return Err(self.error(format!("{} is already defined", name)))
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    /// the labels, and where each byte came from
+    fn symbols(&self) -> Symbols {
+        let mut symbols = Symbols::new();
+        let mut labels: Vec<(&String, u16)> = self.symbols.iter().filter_map(|(name, s)| match s {
+            Symbol::Label(addr) => Some((name, *addr)),
+            Symbol::Const(_) => None
+        }).collect();
+        labels.sort();
+        for (name, addr) in labels {
+            symbols.add_label(addr, name)
+        }
+        // runs of bytes from the same line get one entry
+        let mut run: Option<(usize, usize, Loc)> = None;
+        let mut addr = ROM_START;
+        for (item, loc) in &self.items {
+            run = match run {
+                Some((start, len, l)) if l == *loc => Some((start, len + item.len(), l)),
+                _ => {
+                    if let Some(run) = run {
+                        self.add_run(&mut symbols, run)
+                    }
+                    Some((addr, item.len(), *loc))
+                }
+            };
+            addr += item.len()
+        }
+        if let Some(run) = run {
+            self.add_run(&mut symbols, run)
+        }
+        symbols
+    }
+    fn add_run(&self, symbols: &mut Symbols, (start, len, loc): (usize, usize, Loc)) {
+        if len > 0 {
+            let file = self.files[loc.file].as_deref().unwrap_or("<source>");
+            symbols.add_line(start as u16, len as u16, loc.line, file)
+        }
+    }
+
+    /// resolve every expression and produce the rom
+    fn rom(&self) -> Result<Vec<u8>, Avc2Error> {
+        if self.size > MAX_ROM_SIZE {
+            return Err(Avc2Error::AsmError(format!("the code is too large ({} bytes, the limit is 0x{:x})", self.size, MAX_ROM_SIZE)))
+        }
+        let mut code = ROM_HEADER.to_vec();
+        for (item, loc) in &self.items {
+            let addr = ROM_START + code.len() - ROM_HEADER.len();
+            match item {
+                Item::Bytes(b) => code.extend(b),
+                Item::Value(width, expr) => {
+                    let err = |e| self.error_at(*loc, e);
+                    let v = self.eval(expr, 0).map_err(err)?;
+                    match width {
+                        Width::Byte => code.push(fit(v, -0x80, 0xff, "byte").map_err(err)? as u8),
+                        Width::Word => {
+                            let w = fit(v, -0x8000, 0xffff, "word").map_err(err)? as u16;
+                            code.extend(w.to_be_bytes())
+                        }
+                        Width::Relative => {
+                            // the offset is applied to the address of the instruction after it
+                            let from = (addr + 1) as i64;
+                            let ofs = v - from;
+                            if !(-0x80..=0x7f).contains(&ofs) {
+                                return Err(err(format!("{:04x} is out of range of a relative offset from {:04x} ({})", v, from, ofs)))
+                            }
+                            code.push(ofs as u8)
+                        }
+                    }
+                }
+            }
+        }
+        Ok(code)
+    }
+
+    fn eval(&self, expr: &Expr, depth: usize) -> Result<i64, String> {
+        Ok(match expr {
+            Expr::Num(n) => *n,
+            Expr::Name(name) => match self.symbols.get(name) {
+                Some(Symbol::Label(addr)) => *addr as i64,
+                Some(Symbol::Const(e)) => {
+                    if depth >= MAX_DEPTH {
+                        return Err(format!("{} is defined in terms of itself", name))
+                    }
+                    self.eval(e, depth + 1)?
+                }
+                None => return Err(format!("{} is not defined", name))
+            }
+            Expr::Neg(e) => self.eval(e, depth)?.wrapping_neg(),
+            Expr::Not(e) => !self.eval(e, depth)?,
+            Expr::Bin(op, a, b) => {
+                let (a, b) = (self.eval(a, depth)?, self.eval(b, depth)?);
+                match op {
+                    Op::Add => a.wrapping_add(b),
+                    Op::Sub => a.wrapping_sub(b),
+                    Op::Mul => a.wrapping_mul(b),
+                    Op::Div => a.checked_div(b).ok_or("division by zero")?,
+                    Op::Rem => a.checked_rem(b).ok_or("division by zero")?,
+                    Op::And => a & b,
+                    Op::Or => a | b,
+                    Op::Xor => a ^ b,
+                    Op::Shl => a.checked_shl(b as u32).unwrap_or(0),
+                    Op::Shr => a.checked_shr(b as u32).unwrap_or(0)
+                }
+            }
+        })
+    }
+}
+
+/// check a value fits in a byte or word. negative values are allowed, as two's complement
+fn fit(v: i64, min: i64, max: i64, what: &str) -> Result<i64, String> {
+    if (min..=max).contains(&v) {
+        Ok(v)
+    }
+    else {
+        Err(format!("{} doesn't fit in a {}", v, what))
+    }
+}
+
+/// split a line into words at whitespace, leaving whitespace inside brackets and quotes alone,
+/// and drop any comment
+fn split_words(line: &str) -> Result<Vec<&str>, String> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut depth = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if in_quotes {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quotes = false,
+                _ => {}
+            }
+            continue
+        }
+        if c == '/' && chars.peek().map(|(_, c)| *c) == Some('/') && depth == 0 {
+            break
+        }
+        if c.is_whitespace() && depth == 0 {
+            if let Some(s) = start.take() {
+                words.push(&line[s..i])
+            }
+            continue
+        }
+        if start.is_none() {
+            start = Some(i)
+        }
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err(String::from("unmatched )")),
+            ')' => depth -= 1,
+            '"' => in_quotes = true,
+            _ => {}
+        }
+    }
+    if in_quotes {
+        return Err(String::from("unterminated string"))
+    }
+    if depth != 0 {
+        return Err(String::from("unmatched ("))
+    }
+    if let Some(s) = start {
+        words.push(&line[s..])
+    }
+    Ok(words)
+}
+
+/// the arguments of a directive like `.name(args)`
+fn directive_args(word: &str) -> Option<&str> {
+    word.split_once('(')?.1.strip_suffix(')')
+}
+
+/// split a list of arguments at commas outside brackets and quotes
+fn split_args(s: &str) -> Vec<&str> {
+    let mut args = Vec::new();
+    let (mut depth, mut in_quotes, mut escaped, mut start) = (0, false, false, 0);
+    for (i, c) in s.char_indices() {
+        if in_quotes {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quotes = false,
+                _ => {}
+            }
+            continue
+        }
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '"' => in_quotes = true,
+            ',' if depth == 0 => {
+                args.push(s[start..i].trim());
+                start = i + 1
+            }
+            _ => {}
+        }
+    }
+    if !s[start..].trim().is_empty() || !args.is_empty() {
+        args.push(s[start..].trim())
+    }
+    args
+}
+
+/// a quoted string, with the escapes `\n`, `\t`, `\0`, `\\`, `\"` and `\xhh`
+fn parse_string(s: &str) -> Result<Vec<u8>, String> {
+    let inner = s.trim().strip_prefix('"').and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a quoted string, not {}", s.trim()))?;
+    let mut out = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            out.extend(c.encode_utf8(&mut buf).as_bytes());
+            continue
+        }
+        match chars.next() {
+            Some('n') => out.push(b'\n'),
+            Some('t') => out.push(b'\t'),
+            Some('0') => out.push(0),
+            Some('\\') => out.push(b'\\'),
+            Some('"') => out.push(b'"'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                out.push(u8::from_str_radix(&hex, 16).map_err(|_| format!("bad escape \\x{}", hex))?)
+            }
+            Some(c) => return Err(format!("bad escape \\{}", c)),
+            None => return Err(String::from("bad escape at the end of a string"))
+        }
+    }
+    Ok(out)
+}
+
+/// replace the parameters of a macro in a line of its body, and `@` with `unique`.
+/// names straight after a `.` (directives) and text in quotes are left alone
+fn substitute(line: &str, params: &[String], args: &[&str], unique: usize) -> String {
+    let mut out = String::new();
+    let mut chars = line.char_indices().peekable();
+    let (mut in_quotes, mut escaped, mut after_dot) = (false, false, false);
+    while let Some((i, c)) = chars.next() {
+        if in_quotes {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quotes = false,
+                _ => {}
+            }
+            out.push(c);
+            continue
+        }
+        if is_name_start(c) {
+            let mut end = i + c.len_utf8();
+            while let Some(&(j, c)) = chars.peek() {
+                if !is_name_char(c) {
+                    break
+                }
+                end = j + c.len_utf8();
+                chars.next();
+            }
+            let name = &line[i..end];
+            match params.iter().position(|p| p == name) {
+                Some(n) if !after_dot => out.push_str(args[n]),
+                _ => out.push_str(name)
+            }
+            after_dot = false;
+            continue
+        }
+        match c {
+            '@' => out.push_str(&unique.to_string()),
+            '"' => {
+                in_quotes = true;
+                out.push(c)
+            }
+            _ => out.push(c)
+        }
+        after_dot = c == '.'
+    }
+    out
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+fn check_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    if chars.next().is_some_and(is_name_start) && chars.all(is_name_char) {
+        Ok(())
+    }
+    else {
+        Err(format!("bad name {:?}", name))
+    }
+}
+
+/// every mnemonic, mapped to its byte
+fn mnemonic_table() -> HashMap<String, u8> {
+    (0..=0xff).filter_map(|b| opcodes::info(b).mnemonic.clone().map(|m| (m, b))).collect()
+}
+
+/// look up a mnemonic. the mode suffixes can come in any order, so `LIT2r` works as well as
+/// `LITr2`
+fn lookup(mnemonics: &HashMap<String, u8>, word: &str) -> Option<u8> {
+    if let Some(&b) = mnemonics.get(word) {
+        return Some(b)
+    }
+    if !word.is_ascii() { // no mnemonic is, and splitting could land inside a char
+        return None
+    }
+    let (name, suffix) = word.split_at(word.len().min(3));
+    let mut canonical = String::from(name);
+    for mode in ['k', 'r', '2'] {
+        match suffix.matches(mode).count() {
+            0 => {}
+            1 => canonical.push(mode),
+            _ => return None
+        }
+    }
+    if canonical.len() != word.len() { // something other than a mode
+        return None
+    }
+    mnemonics.get(&canonical).copied()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Add, Sub, Mul, Div, Rem, And, Or, Xor, Shl, Shr
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Num(i64),
+    Name(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Bin(Op, Box<Expr>, Box<Expr>)
+}
+
+// binary operators, loosest binding first
+const PRECEDENCE: [&[(&str, Op)]; 5] = [
+    &[("|", Op::Or)],
+    &[("^", Op::Xor)],
+    &[("&", Op::And)],
+    &[("<<", Op::Shl), (">>", Op::Shr)],
+    &[("+", Op::Add), ("-", Op::Sub)],
+];
+const PRODUCT: [(&str, Op); 3] = [("*", Op::Mul), ("/", Op::Div), ("%", Op::Rem)];
+
+impl Expr {
+    fn parse(s: &str) -> Result<Expr, String> {
+        let mut p = ExprParser { s: s.trim(), pos: 0 };
+        let e = p.binary(0)?;
+        p.skip_space();
+        if p.pos != p.s.len() {
+            return Err(format!("unexpected {:?} in expression {:?}", &p.s[p.pos..], s.trim()))
+        }
+        Ok(e)
+    }
+}
+
+struct ExprParser<'a> {
+    s: &'a str,
+    pos: usize
+}
+
+impl ExprParser<'_> {
+    fn skip_space(&mut self) {
+        while let Some(c) = self.rest().chars().next().filter(|c| c.is_whitespace()) {
+            self.pos += c.len_utf8()
+        }
+    }
+    fn rest(&self) -> &str {
+        &self.s[self.pos..]
+    }
+    /// consume one of `ops`, if it's next
+    fn op(&mut self, ops: &[(&str, Op)]) -> Option<Op> {
+        self.skip_space();
+        for (text, op) in ops {
+            if self.rest().starts_with(text) {
+                self.pos += text.len();
+                return Some(*op)
+            }
+        }
+        None
+    }
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        let ops = if level < PRECEDENCE.len() { PRECEDENCE[level] } else { &PRODUCT[..] };
+        let next = |p: &mut Self| if level < PRECEDENCE.len() { p.binary(level + 1) } else { p.unary() };
+        let mut e = next(self)?;
+        while let Some(op) = self.op(ops) {
+            e = Expr::Bin(op, Box::new(e), Box::new(next(self)?))
+        }
+        Ok(e)
+    }
+    fn unary(&mut self) -> Result<Expr, String> {
+        self.skip_space();
+        let s = self.s;
+        let rest = &s[self.pos..];
+        if rest.starts_with('-') {
+            self.pos += 1;
+            return Ok(Expr::Neg(Box::new(self.unary()?)))
+        }
+        if rest.starts_with('~') {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)))
+        }
+        if rest.starts_with('(') {
+            self.pos += 1;
+            let e = self.binary(0)?;
+            self.skip_space();
+            if !self.rest().starts_with(')') {
+                return Err(format!("missing ) in expression {:?}", self.s))
+            }
+            self.pos += 1;
+            return Ok(e)
+        }
+        let len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        let token = &rest[..len];
+        self.pos += len;
+        if token.starts_with(|c: char| c.is_ascii_digit()) {
+            let (digits, radix) = if let Some(h) = token.strip_prefix("0x") {
+                (h, 16)
+            }
+            else if let Some(b) = token.strip_prefix("0b") {
+                (b, 2)
+            }
+            else {
+                (token, 10)
+            };
+            i64::from_str_radix(digits, radix).map(Expr::Num).map_err(|_| format!("bad number {}", token))
+        }
+        else if token.starts_with(is_name_start) {
+            Ok(Expr::Name(token.to_string()))
+        }
+        else {
+            Err(format!("expected a value in expression {:?}", self.s))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{Processor, Status};
+
+    #[test]
+    fn test_bootstrap() {
+        let rom = assemble(include_str!("../examples/bootstrap.avc")).unwrap();
+        assert_eq!(rom, include_bytes!("../examples/bootstrap.avcr"));
+        assert_eq!(strip_header(&rom).map(|c| c.len()), Some(rom.len() - 4));
+        assert_eq!(strip_header(&rom[..2]), None);
+    }
+
+    #[test]
+    fn test_hello() {
+        let rom = assemble_file(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/hello.avc")).unwrap();
+        assert_eq!(rom, include_bytes!("../examples/hello.avcr"));
+    }
+
+    #[test]
+    fn test_mnemonics() {
+        let rom = assemble("LIT2r .x(1) STA2kr RTI SEC ADCk2r // comment LIT\nSFTkr2").unwrap();
+        assert_eq!(rom[4..], [0xe0, 0x01, 0xf5, 0x83, 0x20, 0xf8, 0xff]);
+        for bad in ["LITk", "POPk", "JMP22", "FOO", ".x(100)"] {
+            assert!(assemble(bad).is_err(), "{} assembled", bad);
+        }
+        for bad in ["ab日", "LIT日", "日"] {
+            assert!(assemble(bad).unwrap_err().to_string().contains("unknown instruction"), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_expressions() {
+        let rom = assemble("
+            .def(A, B * 2) .def(B, (1 + 2) << 4)
+            .b(A) .b(-1) .w(A - 0x100 | 0b1) .b(~0 & 0xf) .w(end) .b(7 % 4) .b(1\u{3000}+\u{a0}2) end:
+        ").unwrap();
+        assert_eq!(rom[4..], [0x60, 0xff, 0xff, 0x61, 0x0f, 0x03, 0x09, 0x03, 0x03]);
+        for bad in [".b(256)", ".b(x)", ".def(X, X)\n.b(X)", "a: a:", ".b(1 +)", ".b(1 / 0)"] {
+            assert!(assemble(bad).is_err(), "{} assembled", bad);
+        }
+    }
+
+    #[test]
+    fn test_labels() {
+        // count down from 5, adding to a total, then halt with the total
+        let src = "
+            .def(HALT, 0xff0f)
+            LIT .x(0) LIT .x(5) // total, counter
+            loop:
+                CLC DUP ROT ADC SWP // total += counter
+                CLC LIT .x(ff) ADC // counter -= 1
+                DUP LIT .r(loop) JNZ
+            POP LIT2 .w(HALT) STA
+        ";
+        let rom = assemble(src).unwrap();
+        let mut p = Processor::new(&rom[4..], Vec::new()).unwrap();
+        assert_eq!(p.run(), Status::Halted(15));
+
+        let far = format!("start: {} LIT .r(start) JMP", "LIT .x(0) ".repeat(64));
+        assert!(assemble(&far).is_err());
+        let near = format!("start: {} LIT .r(start) JMP", "LIT .x(0) ".repeat(63));
+        assert!(assemble(&near).is_ok());
+    }
+
+    #[test]
+    fn test_macros() {
+        let src = r#"
+            .macro(PUSH2, a, b) // push two bytes
+                LIT .b(a) LIT .b(b)
+            .end
+            .macro(SPIN)
+                loop@: LIT .r(loop@) JMP
+            .end
+            PUSH2(1, 2 + 3) SPIN SPIN .b(b)
+            .def(b, 7)
+            .str("hi\n") .fat("a\x00")
+        "#;
+        let rom = assemble(src).unwrap();
+        assert_eq!(rom[4..], [0x80, 1, 0x80, 5, 0x80, 0xfe, 0x0a, 0x80, 0xfe, 0x0a, 7, b'h', b'i', b'\n', 0, 2, b'a', 0]);
+        for bad in [".macro(M)\nPOP", "M", ".macro(M, a)\n.end\nM", ".macro(POP)\n.end", ".end", ".str(hi)", ".macro(M)\nM\n.end\nM", ".macro(ab日)\n.end"] {
+            assert!(assemble(bad).is_err(), "{:?} assembled", bad);
+        }
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("avc2-asm-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.avc"), ".include(\"lib/halt.avc\") .bin(\"lib/data.bin\") HALT(3)").unwrap();
+        fs::write(dir.join("lib/halt.avc"), ".def(SYS, 0xff00)\n.macro(HALT, code)\nLIT .b(code) LIT2 .w(SYS + 0xf) STA\n.end\n.include(\"data.avc\")").unwrap();
+        fs::write(dir.join("lib/data.avc"), "data: .x(aa)").unwrap();
+        fs::write(dir.join("lib/data.bin"), [1, 2, 3]).unwrap();
+        let rom = assemble_file(dir.join("main.avc"));
+        fs::write(dir.join("bad.avc"), "\n.include(\"lib/missing.avc\")").unwrap();
+        let err = assemble_file(dir.join("bad.avc")).unwrap_err().to_string();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(rom.unwrap()[4..], [0xaa, 1, 2, 3, 0x80, 3, 0xa0, 0xff, 0x0f, 0x15]);
+        assert!(err.contains("bad.avc:2: can't read"), "{}", err);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/hello.avc");
+        let (_, symbols) = assemble_file_with_symbols(path).unwrap();
+        let loop_addr = symbols.label("loop").unwrap();
+        assert_eq!(loop_addr, 0x0303);
+        assert!(symbols.describe(loop_addr + 1).unwrap().starts_with("loop+1 ("));
+        assert!(symbols.describe(loop_addr + 1).unwrap().ends_with("hello.avc:7)"));
+        // the PUTC macro, expanded on line 7
+        assert!(symbols.describe(loop_addr + 3).unwrap().ends_with("hello.avc:7)"));
+        assert!(symbols.describe(symbols.label("done").unwrap() + 2).unwrap().ends_with("hello.avc:13)"));
+    }
+}
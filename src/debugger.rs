@@ -0,0 +1,318 @@
+//! an interactive debugger, built on top of `Processor::execute_once`
+
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+use crate::processor::{Processor, Status};
+use crate::opcodes::{self, Category};
+use crate::symbols::Symbols;
+
+/// why the debugger stopped running the cpu
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// the step (or step over/out) finished
+    Stepped,
+    /// the pc reached a breakpoint
+    Breakpoint(u16),
+    /// the cpu halted or faulted
+    Exited(Status)
+}
+
+pub struct Debugger {
+    processor: Processor,
+    breakpoints: BTreeSet<u16>,
+    symbols: Symbols
+}
+
+impl Debugger {
+    pub fn new(processor: Processor) -> Debugger {
+        Debugger {
+            processor,
+            breakpoints: BTreeSet::new(),
+            symbols: Symbols::new()
+        }
+    }
+    /// show locations with these symbols, and allow labels as addresses
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols
+    }
+    pub fn processor(&self) -> &Processor {
+        &self.processor
+    }
+    pub fn processor_mut(&mut self) -> &mut Processor {
+        &mut self.processor
+    }
+    pub fn into_processor(self) -> Processor {
+        self.processor
+    }
+
+    /// returns false if there was already a breakpoint there
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.insert(addr)
+    }
+    /// returns false if there was no breakpoint there
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+    pub fn breakpoints(&self) -> impl Iterator<Item = &u16> {
+        self.breakpoints.iter()
+    }
+
+    /// execute exactly one instruction
+    pub fn step(&mut self) -> Stop {
+        match self.processor.execute_once() {
+            Status::Running => Stop::Stepped,
+            s => Stop::Exited(s)
+        }
+    }
+    /// run until a breakpoint is reached or the cpu stops. always executes at least one
+    /// instruction, so continuing from a breakpoint doesn't stop straight away
+    pub fn cont(&mut self) -> Stop {
+        self.run_until(|_| false)
+    }
+    /// like `step`, but runs JSR instructions until they return
+    pub fn step_over(&mut self) -> Stop {
+        let p = &self.processor;
+        let instr = p.mem().peek(p.pc());
+        let info = opcodes::info(instr);
+        // JSR is the jump that pushes a return address to the return stack, or the working
+        // stack in return mode
+        let other_stack = instr & 0x40 != 0;
+        let pushed = if other_stack { info.working } else { info.ret };
+        if info.category != Category::Jump || pushed.outputs == 0 {
+            return self.step()
+        }
+        let ret = p.pc().wrapping_add(1);
+        let sp = sp(p, other_stack);
+        self.run_until(|p| p.pc() == ret && sp_at_or_above(p, other_stack, sp))
+    }
+    /// run until the cpu jumps to the address on top of the return stack, with that address
+    /// popped. returns None if the return stack doesn't have an address on it
+    pub fn step_out(&mut self) -> Option<Stop> {
+        let rst = self.processor.return_stack();
+        if rst.len() < 2 {
+            return None
+        }
+        let ret = u16::from_be_bytes([rst[rst.len() - 1], rst[rst.len() - 2]]);
+        let rsp = self.processor.rsp().wrapping_add(2);
+        Some(self.run_until(|p| p.pc() == ret && sp_at_or_above(p, true, rsp)))
+    }
+    fn run_until(&mut self, done: impl Fn(&Processor) -> bool) -> Stop {
+        loop {
+            if let Stop::Exited(s) = self.step() {
+                return Stop::Exited(s)
+            }
+            if done(&self.processor) {
+                return Stop::Stepped
+            }
+            let pc = self.processor.pc();
+            if self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc)
+            }
+        }
+    }
+
+    /// run a command prompt, reading commands from `input` and writing to `out`, until the
+    /// input ends or the user quits
+    pub fn repl(&mut self, input: impl BufRead, mut out: impl Write) -> std::io::Result<()> {
+        writeln!(out, "avc2 debugger. h for help")?;
+        self.print_location(&mut out)?;
+        let mut last = String::new();
+        let mut lines = input.lines();
+        loop {
+            write!(out, "> ")?;
+            out.flush()?;
+            let line = match lines.next() {
+                Some(l) => l?,
+                None => return Ok(())
+            };
+            // an empty line repeats the last command
+            let line = if line.trim().is_empty() { last.clone() } else { line };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (cmd, args) = match words.split_first() {
+                Some((c, a)) => (*c, a),
+                None => continue
+            };
+            match cmd {
+                "q" | "quit" => return Ok(()),
+                "h" | "help" => writeln!(out, "{}", HELP)?,
+                "s" | "step" => {
+                    let n = match args.first().map(|a| a.parse::<u32>()) {
+                        Some(Ok(n)) => n,
+                        Some(Err(_)) => { writeln!(out, "bad step count")?; continue }
+                        None => 1
+                    };
+                    let mut stop = Stop::Stepped;
+                    for _ in 0..n {
+                        stop = self.step();
+                        if stop != Stop::Stepped {
+                            break
+                        }
+                    }
+                    self.report(stop, &mut out)?
+                }
+                "n" | "next" => {
+                    let stop = self.step_over();
+                    self.report(stop, &mut out)?
+                }
+                "f" | "finish" => match self.step_out() {
+                    Some(stop) => self.report(stop, &mut out)?,
+                    None => writeln!(out, "the return stack doesn't hold an address")?
+                }
+                "c" | "continue" => {
+                    let stop = self.cont();
+                    self.report(stop, &mut out)?
+                }
+                "b" | "break" => match args.first() {
+                    Some(a) => match self.addr(a) {
+                        Some(addr) => {
+                            self.add_breakpoint(addr);
+                            writeln!(out, "breakpoint set at {:04x}", addr)?
+                        }
+                        None => writeln!(out, "bad address {}", a)?
+                    }
+                    None => {
+                        for b in &self.breakpoints {
+                            match self.symbols.describe(*b) {
+                                Some(place) => writeln!(out, "{:04x}  {}", b, place)?,
+                                None => writeln!(out, "{:04x}", b)?
+                            }
+                        }
+                    }
+                }
+                "d" | "delete" => match args.first().and_then(|a| self.addr(a)) {
+                    Some(addr) => if !self.remove_breakpoint(addr) {
+                        writeln!(out, "no breakpoint at {:04x}", addr)?
+                    }
+                    None => writeln!(out, "usage: d ADDR")?
+                }
+                "r" | "regs" => self.print_regs(&mut out)?,
+                "x" | "dump" => {
+                    let start = args.first().and_then(|a| self.addr(a));
+                    let len = match args.get(1) {
+                        Some(a) => parse_addr(a),
+                        None => Some(0x40)
+                    };
+                    match (start, len) {
+                        (Some(start), Some(len)) => self.dump(start, len, &mut out)?,
+                        _ => writeln!(out, "usage: x ADDR [LEN]")?
+                    }
+                }
+                _ => writeln!(out, "unknown command {}. h for help", cmd)?
+            }
+            last = line;
+        }
+    }
+
+    fn report(&self, stop: Stop, out: &mut impl Write) -> std::io::Result<()> {
+        match stop {
+            Stop::Stepped => {}
+            Stop::Breakpoint(addr) => writeln!(out, "breakpoint at {:04x}", addr)?,
+            Stop::Exited(Status::Halted(ecode)) => writeln!(out, "halted with exit code {}", ecode)?,
+            Stop::Exited(Status::Faulted{fault, pc, instr}) => writeln!(out, "fault at {:04x} (instruction {:02x}): {}", pc, instr, fault)?,
+            Stop::Exited(Status::Running) => {}
+        }
+        self.print_location(out)
+    }
+    fn print_location(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let p = &self.processor;
+        let instr = p.mem().peek(p.pc());
+        write!(out, "{:04x}  {:02x}  {}", p.pc(), instr, opcodes::describe(instr))?;
+        match self.symbols.describe(p.pc()) {
+            Some(place) => writeln!(out, "  {}", place),
+            None => writeln!(out)
+        }
+    }
+    /// a label, or a hex address
+    fn addr(&self, s: &str) -> Option<u16> {
+        self.symbols.label(s).or_else(|| parse_addr(s))
+    }
+    fn print_regs(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let p = &self.processor;
+        writeln!(out, "pc {:04x}  st {:02x}  wsp {:02x}  rsp {:02x}", p.pc(), p.st(), p.wsp(), p.rsp())?;
+        writeln!(out, "wst: {}", hex_bytes(&p.working_stack()))?;
+        writeln!(out, "rst: {}", hex_bytes(&p.return_stack()))
+    }
+    fn dump(&self, start: u16, len: u16, out: &mut impl Write) -> std::io::Result<()> {
+        let end = (start as u32 + len as u32).min(0x10000);
+        let mut addr = start as u32;
+        while addr < end {
+            let line_end = (addr + 16).min(end);
+            let bytes: Vec<u8> = (addr..line_end).map(|a| self.processor.mem().peek(a as u16)).collect();
+            writeln!(out, "{:04x}  {}", addr, hex_bytes(&bytes))?;
+            addr = line_end
+        }
+        Ok(())
+    }
+}
+
+const HELP: &str = "\
+s [N]         step N instructions (default 1)
+n             step, running JSRs until they return
+f             run until the address on the return stack is returned to
+c             continue until a breakpoint, halt or fault
+b [ADDR]      set a breakpoint, or list them. ADDR can be a label
+d ADDR        delete a breakpoint
+r             show registers and stacks
+x ADDR [LEN]  hex dump LEN bytes (default 40) from ADDR
+q             quit
+numbers are hex. labels come from the rom's .sym file. an empty line repeats the last command. the device page reads as 00";
+
+fn sp(p: &Processor, is_rst: bool) -> u8 {
+    if is_rst { p.rsp() } else { p.wsp() }
+}
+/// whether a stack has shrunk back to (or past) where it was, so recursive calls don't count
+fn sp_at_or_above(p: &Processor, is_rst: bool, old: u8) -> bool {
+    sp(p, is_rst) >= old
+}
+
+fn parse_addr(s: &str) -> Option<u16> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+fn hex_bytes(b: &[u8]) -> String {
+    b.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom() -> Vec<u8> {
+        // 0300 LIT 05   0302 JSR (-> 0307)   0303 LIT2 ff0f   0306 STA (halt)
+        // 0307 LIT 2a   0309 JMPr2
+        vec![0x80, 0x05, 0x0c, 0xa0, 0xff, 0x0f, 0x15, 0x80, 0x2a, 0x6a]
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let mut d = Debugger::new(Processor::new(&rom(), Vec::new()).unwrap());
+        assert_eq!(d.step(), Stop::Stepped);
+        assert_eq!(d.step_over(), Stop::Stepped);
+        assert_eq!(d.processor().pc(), 0x0303);
+        assert_eq!(d.processor().working_stack(), vec![0x2a]);
+
+        let mut d = Debugger::new(Processor::new(&rom(), Vec::new()).unwrap());
+        d.step();
+        d.step();
+        assert_eq!(d.processor().pc(), 0x0307);
+        assert_eq!(d.step_out(), Some(Stop::Stepped));
+        assert_eq!(d.processor().pc(), 0x0303);
+        assert_eq!(d.step_out(), None);
+        assert_eq!(d.cont(), Stop::Exited(Status::Halted(0x2a)));
+    }
+
+    #[test]
+    fn test_repl() {
+        let mut d = Debugger::new(Processor::new(&rom(), Vec::new()).unwrap());
+        let mut symbols = Symbols::new();
+        symbols.add_label(0x0309, "ret");
+        symbols.add_line(0x0307, 3, 5, "main.avc");
+        d.set_symbols(symbols);
+        let mut out = Vec::new();
+        d.repl("b ret\nc\nr\nx 300 4\nq\n".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("breakpoint at 0309\n0309  6a  JMPr2  ret (main.avc:5)"));
+        assert!(out.contains("wst: 2a\nrst: 03 03"));
+        assert!(out.contains("0300  80 05 0c a0"));
+    }
+}
@@ -1,6 +1,8 @@
 use super::{Device, WriteResponse};
 use avdrive::{Avd, AvdError};
 use std::path::{PathBuf, Path};
+use std::collections::BTreeSet;
+use crate::snapshot::StateReader;
 use crate::utils::*;
 
 /// DRIVE DEVICE
@@ -12,12 +14,13 @@ use crate::utils::*;
 /// 4   w   page
 /// 8   w   read, drive -> mem
 /// 9   w   write, mem -> drive
-
 pub struct Drive {
     drive: Avd,
     archive_path: PathBuf,
     block: u16,
-    page: u8
+    page: u8,
+    /// blocks written since the drive was loaded, so snapshots don't need the whole drive
+    dirty: BTreeSet<u16>
 }
 impl Drive {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Drive, AvdError> {
@@ -26,7 +29,8 @@ impl Drive {
         Ok(Drive {
             drive, archive_path,
             block: 0,
-            page: 0
+            page: 0,
+            dirty: BTreeSet::new()
         })
     }
 }
@@ -64,8 +68,51 @@ impl Device for Drive {
     fn shutdown(&mut self) {
         let _ = self.drive.save(&self.archive_path);
     }
-    fn dma_callback(&mut self, data: Vec<u8>) {
-        assert!(data.len() == 256);
-        self.drive.set_block(self.block, &data.try_into().unwrap())
+    fn dma_callback(&mut self, data: Vec<u8>) -> Result<(), Fault> {
+        let l = data.len();
+        let block = data.try_into().map_err(|_| Fault::BadDeviceAccess(format!("drive expected 256 bytes of dma, got {}", l)))?;
+        self.drive.set_block(self.block, &block);
+        self.dirty.insert(self.block);
+        Ok(())
+    }
+    fn devid(&self) -> u8 {
+        2
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.block.to_be_bytes().to_vec();
+        state.push(self.page);
+        state.extend((self.dirty.len() as u32).to_be_bytes());
+        for b in &self.dirty {
+            state.extend(b.to_be_bytes());
+            state.extend(self.drive.get_block(*b).unwrap_or([0; 256]))
+        }
+        state
+    }
+    fn check_state(&self, state: &[u8]) -> Result<(), Avc2Error> {
+        parse_state(state).map(|_| ())
     }
+    fn load_state(&mut self, state: &[u8]) -> Result<(), Avc2Error> {
+        let state = parse_state(state)?;
+        self.block = state.block;
+        self.page = state.page;
+        for (b, data) in state.blocks {
+            self.drive.set_block(b, data.try_into().unwrap());
+            self.dirty.insert(b);
+        }
+        Ok(())
+    }
+}
+
+struct State<'a> {
+    block: u16,
+    page: u8,
+    /// every dirty block and its contents
+    blocks: Vec<(u16, &'a [u8])>
+}
+fn parse_state(state: &[u8]) -> Result<State<'_>, Avc2Error> {
+    let mut r = StateReader::new(state);
+    let (block, page) = (r.u16()?, r.u8()?);
+    let blocks = (0..r.u32()?).map(|_| Ok((r.u16()?, r.bytes(256)?))).collect::<Result<_, Avc2Error>>()?;
+    r.finish()?;
+    Ok(State { block, page, blocks })
 }
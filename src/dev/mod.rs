@@ -1,14 +1,22 @@
-use system::System;
-use drive::Drive;
-use crate::utils::Avc2Error;
+pub use system::System;
+pub use drive::Drive;
+pub use timer::Timer;
+pub use script::InputScript;
+use crate::utils::{Avc2Error, Fault};
 use crate::memory::DmaRequest;
+use std::mem::take;
 
 mod system;
 mod drive;
+mod timer;
+mod script;
 
 pub struct DevicePage {
     devs: [Option<Box<dyn Device>>; 16],
-    last_dma_dev: u8
+    last_dma_dev: u8,
+    halt: Option<u8>,
+    snapshot_requested: bool,
+    is_shut_down: bool
 }
 
 impl DevicePage {
@@ -33,26 +41,47 @@ impl DevicePage {
                     let d = Drive::new(spec.options[0])?;
                     devs[spec.loc] = Some(Box::new(d));
                 }
+                3 => { // timer
+                    if !spec.options.is_empty() {
+                        return Err(Avc2Error::DevInitError(String::from("the timer device takes no options")))
+                    }
+                    devs[spec.loc] = Some(Box::new(Timer::new()));
+                }
                 _ => return Err(Avc2Error::DevInitError(format!("unrecognised devid {}", spec.id)))
             }
         }
 
         Ok(DevicePage {
             devs,
-            last_dma_dev: 0
+            last_dma_dev: 0,
+            halt: None,
+            snapshot_requested: false,
+            is_shut_down: false
         })
     }
+    pub fn attach(&mut self, slot: usize, dev: Box<dyn Device>) -> Result<(), Avc2Error> {
+        if slot == 0 && dev.devid() != 1 {
+            return Err(Avc2Error::DevInitError(String::from("dev 0 must be system")))
+        }
+        if slot >= 16 {
+            return Err(Avc2Error::DevInitError(String::from("device locations greater than 15 are invalid")))
+        }
+        // whatever was there is shut down first, so a drive doesn't lose its writes
+        if let Some(mut old) = self.devs[slot].replace(dev) {
+            old.shutdown()
+        }
+        Ok(())
+    }
     pub fn write(&mut self, addr: u8, val: u8) -> Option<DmaRequest> {
         let dev_idx = addr / 16;
         let addr = addr % 16;
         if let Some(d) = &mut self.devs[dev_idx as usize] {
             match d.write(addr, val) {
                 WriteResponse::Shutdown(ecode) => {
-                    for dev in &mut self.devs {
-                        dev.as_mut().map(|d| d.shutdown());
-                    }
-                    println!();
-                    std::process::exit(ecode as i32)
+                    self.halt = Some(ecode)
+                }
+                WriteResponse::Snapshot => {
+                    self.snapshot_requested = true
                 }
                 WriteResponse::DmaToMem{addr, data} => {
                     return Some(DmaRequest::ToMem{addr, data})
@@ -76,10 +105,81 @@ impl DevicePage {
             0
         }
     }
-    pub fn dma_callback(&mut self, data: Vec<u8>) {
+    pub fn dma_callback(&mut self, data: Vec<u8>) -> Result<(), Fault> {
         if let Some(d) = &mut self.devs[self.last_dma_dev as usize] {
             d.dma_callback(data)
         }
+        else {
+            Ok(())
+        }
+    }
+    /// advance every device by one instruction
+    pub fn tick(&mut self) {
+        for d in self.devs.iter_mut().flatten() {
+            d.tick()
+        }
+    }
+    /// advance every device by `n` instructions. devices can't see each other's ticks, so each
+    /// one takes all of its ticks in a row
+    pub fn tick_n(&mut self, n: u32) {
+        for d in self.devs.iter_mut().flatten() {
+            for _ in 0..n {
+                d.tick()
+            }
+        }
+    }
+    /// the lowest numbered device with its irq line asserted
+    pub fn pending_irq(&self) -> Option<u8> {
+        self.devs.iter().position(|d| d.as_ref().is_some_and(|d| d.irq())).map(|i| i as u8)
+    }
+    pub fn ack_irq(&mut self, dev_idx: u8) {
+        if let Some(d) = &mut self.devs[dev_idx as usize] {
+            d.ack_irq()
+        }
+    }
+    pub fn take_snapshot_request(&mut self) -> bool {
+        take(&mut self.snapshot_requested)
+    }
+    /// the devid and saved state of each slot. devices without a devid are left out
+    pub fn states(&self) -> Vec<Option<(u8, Vec<u8>)>> {
+        self.devs.iter().map(|d| d.as_ref().filter(|d| d.devid() != 0).map(|d| (d.devid(), d.save_state()))).collect()
+    }
+    /// every slot is checked before any device is touched, so a mismatch or a bad state leaves
+    /// them all as they were
+    pub fn load_states(&mut self, states: &[Option<(u8, &[u8])>]) -> Result<(), Avc2Error> {
+        for (i, (dev, state)) in self.devs.iter().zip(states).enumerate() {
+            let id = dev.as_ref().map(|d| d.devid()).filter(|&id| id != 0);
+            if id != state.map(|(id, _)| id) {
+                return Err(Avc2Error::BadSnapshot(format!("device in slot {} doesn't match", i)))
+            }
+            if let (Some(d), Some((_, state))) = (dev, state) {
+                d.check_state(state).map_err(|e| Avc2Error::BadSnapshot(format!("device in slot {}: {}", i, e)))?
+            }
+        }
+        for (dev, state) in self.devs.iter_mut().zip(states) {
+            if let (Some(d), Some((_, state))) = (dev, state) {
+                d.load_state(state)?
+            }
+        }
+        Ok(())
+    }
+    /// the exit code, if a device has asked to halt
+    pub fn halt_code(&self) -> Option<u8> {
+        self.halt
+    }
+    /// shut down every device. only the first call does anything
+    pub fn shutdown(&mut self) {
+        if !self.is_shut_down {
+            for d in self.devs.iter_mut().flatten() {
+                d.shutdown()
+            }
+            self.is_shut_down = true
+        }
+    }
+}
+impl Drop for DevicePage {
+    fn drop(&mut self) {
+        self.shutdown()
     }
 }
 
@@ -89,16 +189,47 @@ impl DevicePage {
 /// used by no other device spec
 /// 
 /// all other bytes can be used however you like
-trait Device {
+/// 
+/// `addr` is always the offset into the device's 16 byte port range
+pub trait Device {
     fn write(&mut self, addr: u8, val: u8) -> WriteResponse;
     fn read(&mut self, addr: u8) -> u8;
+    /// the value the DEVID port returns. devices that leave it at 0 aren't saved in snapshots
+    fn devid(&self) -> u8 {
+        0
+    }
+    /// the device's internal state, for snapshots
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    /// fail if `state` wouldn't load, without loading it. snapshots check every device before
+    /// loading any, so a bad one leaves the whole machine as it was
+    fn check_state(&self, _state: &[u8]) -> Result<(), Avc2Error> {
+        Ok(())
+    }
+    /// only called with a state that passed `check_state`
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), Avc2Error> {
+        Ok(())
+    }
     fn shutdown(&mut self) {}
-    fn dma_callback(&mut self, _data: Vec<u8>) {}
+    fn dma_callback(&mut self, _data: Vec<u8>) -> Result<(), Fault> {
+        Ok(())
+    }
+    /// called once per instruction executed
+    fn tick(&mut self) {}
+    /// whether the device's interrupt line is asserted. it should stay asserted until acknowledged
+    fn irq(&self) -> bool {
+        false
+    }
+    /// called when the cpu takes the device's interrupt
+    fn ack_irq(&mut self) {}
 }
 
 pub enum WriteResponse {
     None,
     Shutdown(u8),
+    /// ask the host to save a snapshot
+    Snapshot,
     DmaToMem{addr: u16, data: Vec<u8>},
     DmaToDev{addr: u16, len: u16}
 }
@@ -108,15 +239,21 @@ pub struct DevSpec<'a> {
     options: Vec<&'a str>
 }
 impl DevSpec<'_> {
-    pub fn new(loc: usize, id: u8, opt_string: &str) -> DevSpec {
-        let options = opt_string.split(';').collect();
+    pub fn new(loc: usize, id: u8, opt_string: &str) -> DevSpec<'_> {
+        let options = if opt_string.is_empty() {
+            Vec::new()
+        }
+        else {
+            opt_string.split(';').collect()
+        };
         DevSpec {
             loc, id, options
         }
     }
-    pub fn from_str(s: &str) -> Result<DevSpec, Avc2Error> {
+    #[allow(clippy::should_implement_trait)] // borrows from s, so FromStr doesn't fit
+    pub fn from_str(s: &str) -> Result<DevSpec<'_>, Avc2Error> {
         let (locs, rest) = s.split_once(';').ok_or(Avc2Error::BadDevSpec(String::from(s)))?;
-        let (ids, opts) = rest.split_once(';').ok_or(Avc2Error::BadDevSpec(String::from(s)))?;
+        let (ids, opts) = rest.split_once(';').unwrap_or((rest, ""));
         let loc = locs.parse().map_err(|_| Avc2Error::BadDevSpec(String::from(s)))?;
         let id = ids.parse().map_err(|_| Avc2Error::BadDevSpec(String::from(s)))?;
         Ok(DevSpec::new(loc, id, opts))
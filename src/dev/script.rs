@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use crate::utils::Avc2Error;
+
+/// terminal input, timed by instruction count
+/// 
+/// the text format is one event per line: the instruction count at which the bytes arrive
+/// (in decimal), then the bytes in hex, all separated by spaces. blank lines and lines starting
+/// with `#` are ignored. a recording also starts with a `seed` line, giving the seed of the
+/// random number generator
+/// 
+/// ```text
+/// # type "hi" after a while
+/// seed 1234
+/// 1000 68 69 0a
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputScript {
+    events: VecDeque<(u64, Vec<u8>)>,
+    seed: Option<u16>
+}
+
+impl InputScript {
+    pub fn new() -> InputScript {
+        InputScript::default()
+    }
+    pub fn parse(s: &str) -> Result<InputScript, Avc2Error> {
+        let mut script = InputScript::new();
+        let mut last = 0;
+        for (i, line) in s.lines().enumerate() {
+            let bad_line = || Avc2Error::BadInputScript(format!("line {}: {}", i + 1, line));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let mut words = line.split_whitespace();
+            if line.starts_with("seed") {
+                words.next();
+                script.seed = Some(words.next().and_then(|w| w.parse().ok()).ok_or_else(bad_line)?);
+                continue
+            }
+            let count = words.next().and_then(|w| w.parse().ok()).ok_or_else(bad_line)?;
+            let bytes = words.map(|w| u8::from_str_radix(w, 16)).collect::<Result<Vec<u8>, _>>().map_err(|_| bad_line())?;
+            if count < last { // it would be held back until the event before it
+                return Err(Avc2Error::BadInputScript(format!("line {}: count {} is before the previous event's, {}", i + 1, count, last)))
+            }
+            last = count;
+            script.push(count, &bytes)
+        }
+        Ok(script)
+    }
+    pub fn seed(&self) -> Option<u16> {
+        self.seed
+    }
+    pub fn set_seed(&mut self, seed: u16) {
+        self.seed = Some(seed)
+    }
+    /// a single event, as a line of the text format
+    pub fn format_event(count: u64, bytes: &[u8]) -> String {
+        let mut s = count.to_string();
+        for b in bytes {
+            s.push_str(&format!(" {:02x}", b))
+        }
+        s
+    }
+    /// add bytes arriving at `count`. events must be added in order, or they'll be held back
+    /// until the ones before them arrive
+    pub fn push(&mut self, count: u64, bytes: &[u8]) {
+        self.events.push_back((count, bytes.to_vec()))
+    }
+    /// remove and return every byte that has arrived by `count`
+    pub fn take_until(&mut self, count: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        while let Some((c, _)) = self.events.front() {
+            if *c > count {
+                break
+            }
+            out.extend(self.events.pop_front().unwrap().1)
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for InputScript {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(seed) = self.seed {
+            writeln!(f, "seed {}", seed)?
+        }
+        for (count, bytes) in &self.events {
+            writeln!(f, "{}", InputScript::format_event(*count, bytes))?
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_parse() {
+        let mut s = InputScript::parse("# hi\n\n10 68 69\n20 0a\n").unwrap();
+        assert_eq!(s.to_string(), "10 68 69\n20 0a\n");
+        assert_eq!(s.take_until(9), vec![]);
+        assert_eq!(s.take_until(30), vec![0x68, 0x69, 0x0a]);
+        assert!(InputScript::parse("10 zz").is_err());
+        assert!(InputScript::parse("ten 00").is_err());
+        assert!(InputScript::parse("10 00\n10 01").is_ok());
+        let err = InputScript::parse("20 00\n# oops\n10 01").unwrap_err().to_string();
+        assert!(err.contains("line 3"), "{}", err);
+        let s = InputScript::parse("seed 99\n5 00").unwrap();
+        assert_eq!(s.seed(), Some(99));
+        assert_eq!(s.to_string(), "seed 99\n5 00\n");
+    }
+}
@@ -1,6 +1,8 @@
 #[allow(unused_imports)]
 use std::io::{Read, Write, stdout, stderr, Stdout, Stderr, stdin, Stdin};
-use super::{Device, WriteResponse};
+use super::{Device, WriteResponse, InputScript};
+use crate::snapshot::StateReader;
+use crate::utils::Avc2Error;
 //use rand::{thread_rng, Rng};
 use termion::{AsyncReader, async_stdin, /*raw::{IntoRawMode, RawTerminal}*/};
 use std::thread::sleep;
@@ -12,6 +14,7 @@ use std::time::{Duration, SystemTime};
 /// 0   r   devid, returns 1 
 /// 1   w   wait, wait x ms
 /// 2   r   random, random in range [0, 256)
+/// 3   w   snapshot, ask the host to save a snapshot (avc2 extension)
 /// 8   r   stdin
 /// 9   w   stdout
 /// a   w   stderr
@@ -20,25 +23,67 @@ use std::time::{Duration, SystemTime};
 /// 
 pub struct System {
     lfsr: u16,
+    seed: u16,
     stdout: Stdout,
     stderr: Stderr,
-    stdin: AsyncReader,
-    buf: Vec<u8>
+    input: Input,
+    buf: Vec<u8>,
+    /// instructions executed so far
+    instructions: u64,
+    /// in ms, if WAIT doesn't really sleep
+    virtual_clock: Option<u64>,
+    /// where to record input, in the input script format
+    recorder: Option<Box<dyn Write>>
+}
+
+enum Input {
+    /// only opened on the first read, so that embedders and tests don't grab the terminal
+    Terminal(Option<AsyncReader>),
+    Script(InputScript)
 }
 
 impl System {
     pub fn new() -> Self { 
         let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-        let mut lfsr = time.as_millis() as u16;
-        if lfsr == 0 { lfsr = 1 }
+        System::with_seed(time.as_millis() as u16)
+    }
+    fn with_seed(seed: u16) -> Self {
+        let seed = if seed == 0 { 1 } else { seed };
         System {
-            lfsr,
+            lfsr: seed,
+            seed,
             stdout: stdout(),
             stderr: stderr(),
-            stdin: async_stdin(),
-            buf: Vec::new()
+            input: Input::Terminal(None),
+            buf: Vec::new(),
+            instructions: 0,
+            virtual_clock: None,
+            recorder: None
         }
     }
+    /// a system device that behaves the same on every run: the random numbers come from `seed`,
+    /// WAIT advances a virtual clock instead of sleeping, and there is no terminal input
+    /// (use `set_input_script` to give it some)
+    pub fn deterministic(seed: u16) -> Self {
+        let mut s = System::with_seed(seed);
+        s.input = Input::Script(InputScript::new());
+        s.virtual_clock = Some(0);
+        s
+    }
+    /// take input from a script instead of the terminal
+    pub fn set_input_script(&mut self, script: InputScript) {
+        self.input = Input::Script(script)
+    }
+    /// write every byte of input to `out` as it arrives, stamped with the instruction count, in
+    /// the input script format. the seed goes first, so the recording can replay random numbers too
+    pub fn record_to(&mut self, mut out: Box<dyn Write>) {
+        writeln!(out, "seed {}", self.seed).unwrap();
+        self.recorder = Some(out)
+    }
+    /// the virtual clock in ms, if there is one
+    pub fn virtual_clock(&self) -> Option<u64> {
+        self.virtual_clock
+    }
     fn advance_lfsr(&mut self) {
         self.lfsr ^= self.lfsr >> 7;
         self.lfsr ^= self.lfsr << 9;
@@ -46,7 +91,27 @@ impl System {
         //println!("{}", self.lfsr)
     }
     fn update_buf(&mut self) {
-        self.stdin.read_to_end(&mut self.buf).unwrap();
+        let old_len = self.buf.len();
+        match &mut self.input {
+            Input::Terminal(stdin) => {
+                stdin.get_or_insert_with(async_stdin).read_to_end(&mut self.buf).unwrap();
+            }
+            Input::Script(script) => {
+                self.buf.extend(script.take_until(self.instructions))
+            }
+        }
+        if let Some(out) = &mut self.recorder {
+            if self.buf.len() > old_len {
+                writeln!(out, "{}", InputScript::format_event(self.instructions, &self.buf[old_len..])).unwrap();
+                out.flush().unwrap();
+            }
+        }
+    }
+}
+
+impl Default for System {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -55,17 +120,23 @@ impl Device for System {
         self.advance_lfsr();
         match addr {
             1 => {
-                let d = Duration::from_millis(val as u64);
-                sleep(d)
+                if let Some(clock) = &mut self.virtual_clock {
+                    *clock += val as u64
+                }
+                else {
+                    let d = Duration::from_millis(val as u64);
+                    sleep(d)
+                }
             }
             9 => {
-                self.stdout.write(&[val]).unwrap();
+                self.stdout.write_all(&[val]).unwrap();
                 self.stdout.flush().unwrap();
             }
             0xa => {
-                self.stderr.write(&[val]).unwrap();
+                self.stderr.write_all(&[val]).unwrap();
                 self.stderr.flush().unwrap();
             }
+            3 => return WriteResponse::Snapshot,
             _ => {}
         }
         if addr == 0x0f {
@@ -82,7 +153,7 @@ impl Device for System {
             2 => self.lfsr.to_be_bytes()[0], // random
             8 => {
                 self.update_buf();
-                if self.buf.len() == 0 {
+                if self.buf.is_empty() {
                     0
                 }
                 else {
@@ -101,11 +172,52 @@ impl Device for System {
             _ => 0
         }
     }
+    fn devid(&self) -> u8 {
+        1
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.lfsr.to_be_bytes().to_vec();
+        state.extend((self.buf.len() as u32).to_be_bytes());
+        state.extend(&self.buf);
+        state.extend(self.instructions.to_be_bytes());
+        state.extend(self.virtual_clock.unwrap_or(0).to_be_bytes());
+        state
+    }
+    fn check_state(&self, state: &[u8]) -> Result<(), Avc2Error> {
+        parse_state(state).map(|_| ())
+    }
+    fn load_state(&mut self, state: &[u8]) -> Result<(), Avc2Error> {
+        let (lfsr, buf, instructions, clock) = parse_state(state)?;
+        self.lfsr = lfsr;
+        self.buf = buf.to_vec();
+        self.instructions = instructions;
+        if let Some(c) = &mut self.virtual_clock {
+            *c = clock
+        }
+        if let Input::Script(script) = &mut self.input { // anything before the snapshot is already in buf
+            script.take_until(self.instructions);
+        }
+        Ok(())
+    }
+    fn tick(&mut self) {
+        self.instructions += 1
+    }
     fn shutdown(&mut self) {
         //self.stdout.suspend_raw_mode().unwrap()
     }
 }
 
+/// lfsr, input buffer, instruction count and virtual clock
+fn parse_state(state: &[u8]) -> Result<(u16, &[u8], u64, u64), Avc2Error> {
+    let mut r = StateReader::new(state);
+    let lfsr = r.u16()?;
+    let len = r.u32()? as usize;
+    let buf = r.bytes(len)?;
+    let parsed = (lfsr, buf, r.u64()?, r.u64()?);
+    r.finish()?;
+    Ok(parsed)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
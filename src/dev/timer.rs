@@ -0,0 +1,182 @@
+use super::{Device, WriteResponse};
+use crate::utils::*;
+use crate::snapshot::StateReader;
+
+/// TIMER DEVICE
+/// 
+/// idx typ use
+/// 0   r   devid, returns 3
+/// 1   rw  control. bit 0 run, bit 1 periodic, bit 2 interrupt on expiry
+/// 2   rw  reload hb
+/// 3   rw  reload lb
+/// 4   rw  prescaler. the count goes down once every (prescaler + 1) instructions
+/// 5   r   count hb
+/// 6   r   count lb
+/// 7   r   expired, returns ff if the timer has expired since this was last read
+/// 
+/// starting the timer loads the count from the reload value. when the count reaches 0,
+/// a periodic timer reloads, and a one-shot timer stops. a reload value of 0 counts 65536
+pub struct Timer {
+    control: u8,
+    reload: u16,
+    prescaler: u8,
+    count: u16,
+    prescale_count: u8,
+    expired: bool,
+    irq: bool
+}
+
+const RUN: u8 = 0b001;
+const PERIODIC: u8 = 0b010;
+const IRQ_ENABLE: u8 = 0b100;
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            control: 0,
+            reload: 0,
+            prescaler: 0,
+            count: 0,
+            prescale_count: 0,
+            expired: false,
+            irq: false
+        }
+    }
+    fn from_state(state: &[u8]) -> Result<Timer, Avc2Error> {
+        let mut r = StateReader::new(state);
+        let timer = Timer {
+            control: r.u8()?,
+            reload: r.u16()?,
+            prescaler: r.u8()?,
+            count: r.u16()?,
+            prescale_count: r.u8()?,
+            expired: r.u8()? != 0,
+            irq: r.u8()? != 0
+        };
+        r.finish()?;
+        Ok(timer)
+    }
+}
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Device for Timer {
+    fn read(&mut self, addr: u8) -> u8 {
+        match addr {
+            0 => 3,
+            1 => self.control,
+            2 => self.reload.to_be_bytes()[0],
+            3 => self.reload.to_be_bytes()[1],
+            4 => self.prescaler,
+            5 => self.count.to_be_bytes()[0],
+            6 => self.count.to_be_bytes()[1],
+            7 => {
+                let e = self.expired;
+                self.expired = false;
+                (e as u8) * 0xff
+            }
+            _ => 0
+        }
+    }
+    fn write(&mut self, addr: u8, val: u8) -> WriteResponse {
+        match addr {
+            1 => {
+                if val & RUN != 0 && self.control & RUN == 0 { // starting
+                    self.count = self.reload;
+                    self.prescale_count = 0
+                }
+                self.control = val & (RUN | PERIODIC | IRQ_ENABLE);
+                if self.control & IRQ_ENABLE == 0 {
+                    self.irq = false
+                }
+            }
+            2 => self.reload = set_hb(self.reload, val),
+            3 => self.reload = set_lb(self.reload, val),
+            4 => self.prescaler = val,
+            _ => {}
+        }
+        WriteResponse::None
+    }
+    fn devid(&self) -> u8 {
+        3
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let [rh, rl] = self.reload.to_be_bytes();
+        let [ch, cl] = self.count.to_be_bytes();
+        vec![self.control, rh, rl, self.prescaler, ch, cl, self.prescale_count, self.expired as u8, self.irq as u8]
+    }
+    fn check_state(&self, state: &[u8]) -> Result<(), Avc2Error> {
+        Timer::from_state(state).map(|_| ())
+    }
+    fn load_state(&mut self, state: &[u8]) -> Result<(), Avc2Error> {
+        *self = Timer::from_state(state)?;
+        Ok(())
+    }
+    fn tick(&mut self) {
+        if self.control & RUN == 0 {
+            return
+        }
+        if self.prescale_count < self.prescaler {
+            self.prescale_count += 1;
+            return
+        }
+        self.prescale_count = 0;
+        self.count = self.count.wrapping_sub(1);
+        if self.count == 0 {
+            self.expired = true;
+            if self.control & IRQ_ENABLE != 0 {
+                self.irq = true
+            }
+            if self.control & PERIODIC != 0 {
+                self.count = self.reload
+            }
+            else {
+                self.control &= !RUN
+            }
+        }
+    }
+    fn irq(&self) -> bool {
+        self.irq
+    }
+    fn ack_irq(&mut self) {
+        self.irq = false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_periodic() {
+        let mut t = Timer::new();
+        t.write(2, 0);
+        t.write(3, 3);
+        t.write(4, 1); // every other tick
+        t.write(1, RUN | PERIODIC | IRQ_ENABLE);
+        for _ in 0..5 {
+            t.tick()
+        }
+        assert!(!t.irq());
+        assert_eq!(t.read(6), 1);
+        t.tick();
+        assert!(t.irq());
+        assert_eq!(t.read(7), 0xff);
+        assert_eq!(t.read(7), 0);
+        assert_eq!(t.read(6), 3);
+        t.ack_irq();
+        assert!(!t.irq());
+    }
+    #[test]
+    fn test_one_shot() {
+        let mut t = Timer::new();
+        t.write(3, 2);
+        t.write(1, RUN);
+        t.tick();
+        t.tick();
+        assert_eq!(t.read(1) & RUN, 0);
+        assert!(!t.irq()); // interrupts weren't enabled
+        assert_eq!(t.read(7), 0xff);
+    }
+}
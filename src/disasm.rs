@@ -0,0 +1,114 @@
+//! turning machine code back into assembly
+
+use std::fmt;
+use crate::opcodes::{self, Category};
+
+/// one instruction (or data byte) of a disassembly
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    /// the bytes this line covers, including any literal operand
+    pub bytes: Vec<u8>,
+    /// the assembly for these bytes
+    pub text: String,
+    /// where a relative jump or memory access goes, if the offset was pushed by the
+    /// instruction just before it
+    pub target: Option<u16>
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        write!(f, "{:04x}  {:<10}{}", self.addr, bytes.join(" "), self.text)?;
+        if let Some(t) = self.target {
+            write!(f, " // -> {:04x}", t)?
+        }
+        Ok(())
+    }
+}
+
+/// disassemble `code`, as if it was loaded at `origin`.
+/// bytes with no mnemonic (0x00, undefined bytes and truncated literals) become `.x(..)`
+pub fn disassemble(code: &[u8], origin: u16) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut i = 0;
+    while i < code.len() {
+        let addr = origin.wrapping_add(i as u16);
+        let instr = code[i];
+        let mut text = match opcodes::mnemonic(instr) {
+            Some(m) => m,
+            None => format!(".x({:02x})", instr)
+        };
+        let mut len = 1;
+        let operand_len = opcodes::info(instr).operand as usize;
+        if operand_len > 0 { // LIT, with its operand inline
+            if i + operand_len < code.len() {
+                for b in &code[i + 1..i + 1 + operand_len] {
+                    text.push_str(&format!(" .x({:02x})", b))
+                }
+                len += operand_len
+            }
+            else {
+                text = format!(".x({:02x})", instr)
+            }
+        }
+        let target = lines.last().and_then(|prev| relative_target(prev, addr, instr));
+        lines.push(Line {
+            addr,
+            bytes: code[i..i + len].to_vec(),
+            text,
+            target
+        });
+        i += len
+    }
+    lines
+}
+
+/// a full listing, one line per instruction
+pub fn listing(code: &[u8], origin: u16) -> String {
+    disassemble(code, origin).iter().map(|l| format!("{}\n", l)).collect()
+}
+
+/// relative jumps (JMP, JNZ, JSR) and memory accesses (LDR, STR) take a signed offset from
+/// their own address. if the previous line is an 8-bit LIT onto the same stack, we know it
+fn relative_target(prev: &Line, addr: u16, instr: u8) -> Option<u16> {
+    let lit = opcodes::info(prev.bytes[0]);
+    let same_stack = prev.bytes[0] & 0x40 == instr & 0x40;
+    if !opcodes::info(instr).relative || lit.category != Category::Literal || lit.operand != 1 || prev.bytes.len() != 2 || !same_stack {
+        return None
+    }
+    Some(addr.wrapping_add(prev.bytes[1] as i8 as u16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listing() {
+        let code = [
+            0x80, 0x05, 0x0c, // LIT 05 JSR
+            0xa0, 0xff, 0x0f, 0x15, // LIT2 ff0f STA
+            0xc0, 0xfe, 0x4a, // LITr fe JMPr
+            0x20, 0x83, 0x86, 0x00, // SEC RTI, then an undefined byte and a nop
+            0x80, 0xfd, 0x2a, // LIT fd JMP2 (absolute, so no target)
+            0xa0, 0x01 // truncated LIT2
+        ];
+        assert_eq!(listing(&code, 0x0300), "\
+0300  80 05     LIT .x(05)
+0302  0c        JSR // -> 0307
+0303  a0 ff 0f  LIT2 .x(ff) .x(0f)
+0306  15        STA
+0307  c0 fe     LITr .x(fe)
+0309  4a        JMPr // -> 0307
+030a  20        SEC
+030b  83        RTI
+030c  86        .x(86)
+030d  00        .x(00)
+030e  80 fd     LIT .x(fd)
+0310  2a        JMP2
+0311  a0        .x(a0)
+0312  01        .x(01)
+");
+    }
+}
@@ -0,0 +1,370 @@
+//! a gdb remote serial protocol server, so gdb (or anything else that speaks rsp) can drive
+//! the cpu. registers are pc (16 bits), wsp, rsp and st (8 bits each), sent big endian
+
+use std::io::{self, Read, Write};
+use crate::debugger::{Debugger, Stop};
+use crate::processor::Status;
+use crate::utils::Fault;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.avc2.core">
+    <reg name="pc" bitsize="16" type="code_ptr" regnum="0"/>
+    <reg name="wsp" bitsize="8" type="uint8" regnum="1"/>
+    <reg name="rsp" bitsize="8" type="uint8" regnum="2"/>
+    <reg name="st" bitsize="8" type="uint8" regnum="3"/>
+  </feature>
+</target>
+"#;
+
+// signal numbers for stop replies
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+/// the biggest packet we'll take, advertised in qSupported. reads longer than this are refused
+const PACKET_SIZE: usize = 0x1000;
+
+/// serve one gdb connection over `stream` until gdb detaches or kills the target, or the cpu
+/// halts or faults. returns the final status if the cpu stopped
+pub fn serve(dbg: &mut Debugger, stream: impl Read + Write) -> io::Result<Option<Status>> {
+    let mut conn = Connection { stream };
+    loop {
+        let packet = match conn.read_packet()? {
+            Some(p) => p,
+            None => return Ok(None) // connection closed
+        };
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => stop_reply(SIGTRAP),
+            Some(b'g') => {
+                let p = dbg.processor();
+                let mut regs = p.pc().to_be_bytes().to_vec();
+                regs.extend([p.wsp(), p.rsp(), p.st()]);
+                hex(&regs)
+            }
+            Some(b'G') => match unhex(&packet[1..]) {
+                Some(r) if r.len() == 5 => {
+                    let pc = u16::from_be_bytes([r[0], r[1]]);
+                    dbg.processor_mut().set_registers(r[2], r[3], r[4], pc);
+                    "OK".into()
+                }
+                _ => "E01".into()
+            }
+            Some(b'p') => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(n) => read_register(dbg, n).unwrap_or_else(|| "E01".into()),
+                Err(_) => "E01".into()
+            }
+            Some(b'P') => match write_register(dbg, &packet[1..]) {
+                Some(_) => "OK".into(),
+                None => "E01".into()
+            }
+            Some(b'm') => match parse_range(&packet[1..]) {
+                Some((addr, len)) => {
+                    let bytes: Vec<u8> = (0..len).map(|i| dbg.processor().mem().peek(addr.wrapping_add(i as u16))).collect();
+                    hex(&bytes)
+                }
+                None => "E01".into()
+            }
+            Some(b'M') => match write_memory(dbg, &packet[1..]) {
+                Some(_) => "OK".into(),
+                None => "E01".into()
+            }
+            Some(b'Z') | Some(b'z') => match parse_breakpoint(&packet[1..]) {
+                Some(addr) => {
+                    if packet.starts_with('Z') {
+                        dbg.add_breakpoint(addr);
+                    }
+                    else {
+                        dbg.remove_breakpoint(addr);
+                    }
+                    "OK".into()
+                }
+                None => String::new() // only software breakpoints are supported
+            }
+            Some(b's') | Some(b'c') => {
+                // an address to resume at is optional
+                if packet.len() > 1 {
+                    match u16::from_str_radix(&packet[1..], 16) {
+                        Ok(addr) => dbg.processor_mut().set_pc(addr),
+                        Err(_) => { conn.write_packet("E01")?; continue }
+                    }
+                }
+                let stop = if packet.starts_with('s') { dbg.step() } else { dbg.cont() };
+                match stop {
+                    Stop::Stepped | Stop::Breakpoint(_) => stop_reply(SIGTRAP),
+                    Stop::Exited(Status::Halted(ecode)) => {
+                        conn.write_packet(&format!("W{:02x}", ecode))?;
+                        return Ok(Some(Status::Halted(ecode)))
+                    }
+                    Stop::Exited(status) => {
+                        let sig = match &status {
+                            Status::Faulted{fault, ..} => fault_signal(fault),
+                            _ => SIGTRAP
+                        };
+                        conn.write_packet(&stop_reply(sig))?;
+                        return Ok(Some(status))
+                    }
+                }
+            }
+            Some(b'k') => return Ok(None),
+            Some(b'D') => {
+                conn.write_packet("OK")?;
+                return Ok(None)
+            }
+            Some(b'H') => "OK".into(), // there's only one thread
+            Some(b'q') => query(&packet),
+            _ => String::new() // unsupported
+        };
+        conn.write_packet(&reply)?;
+    }
+}
+
+fn query(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE)
+    }
+    else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        match parse_range(range) {
+            Some((ofs, len)) => {
+                let ofs = (ofs as usize).min(TARGET_XML.len());
+                let end = ofs.saturating_add(len).min(TARGET_XML.len());
+                let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+                format!("{}{}", more, &TARGET_XML[ofs..end])
+            }
+            None => "E01".into()
+        }
+    }
+    else if packet == "qAttached" {
+        "1".into()
+    }
+    else if packet == "qC" {
+        "QC1".into()
+    }
+    else if packet == "qfThreadInfo" {
+        "m1".into()
+    }
+    else if packet == "qsThreadInfo" {
+        "l".into()
+    }
+    else {
+        String::new()
+    }
+}
+
+fn read_register(dbg: &Debugger, n: usize) -> Option<String> {
+    let p = dbg.processor();
+    Some(match n {
+        0 => hex(&p.pc().to_be_bytes()),
+        1 => hex(&[p.wsp()]),
+        2 => hex(&[p.rsp()]),
+        3 => hex(&[p.st()]),
+        _ => return None
+    })
+}
+fn write_register(dbg: &mut Debugger, args: &str) -> Option<()> {
+    let (n, val) = args.split_once('=')?;
+    let n = usize::from_str_radix(n, 16).ok()?;
+    let val = unhex(val)?;
+    let p = dbg.processor_mut();
+    let (mut wsp, mut rsp, mut st, mut pc) = (p.wsp(), p.rsp(), p.st(), p.pc());
+    match (n, val.as_slice()) {
+        (0, &[hb, lb]) => pc = u16::from_be_bytes([hb, lb]),
+        (1, &[v]) => wsp = v,
+        (2, &[v]) => rsp = v,
+        (3, &[v]) => st = v,
+        _ => return None
+    }
+    p.set_registers(wsp, rsp, st, pc);
+    Some(())
+}
+/// `addr,len:bytes`. writes to the device page are refused, since they'd have side effects
+fn write_memory(dbg: &mut Debugger, args: &str) -> Option<()> {
+    let (range, data) = args.split_once(':')?;
+    let (addr, len) = parse_range(range)?;
+    let data = unhex(data)?;
+    let main = dbg.processor_mut().mem_mut().main_mut();
+    let addr = addr as usize;
+    if data.len() != len || addr + len > main.len() {
+        return None
+    }
+    main[addr..addr + len].copy_from_slice(&data);
+    Some(())
+}
+
+/// `addr,len`, both hex. lengths over `PACKET_SIZE` are refused
+fn parse_range(s: &str) -> Option<(u16, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    let len = usize::from_str_radix(len, 16).ok().filter(|&l| l <= PACKET_SIZE)?;
+    Some((u16::from_str_radix(addr, 16).ok()?, len))
+}
+/// `0,addr,kind`, for a software breakpoint
+fn parse_breakpoint(s: &str) -> Option<u16> {
+    let mut parts = s.split(',');
+    if parts.next()? != "0" {
+        return None
+    }
+    u16::from_str_radix(parts.next()?, 16).ok()
+}
+
+fn fault_signal(fault: &Fault) -> u8 {
+    match fault {
+        Fault::DivideByZero => SIGFPE,
+        Fault::DebugBreak => SIGTRAP,
+        Fault::UndefinedInstruction => SIGILL,
+        _ => SIGSEGV
+    }
+}
+fn stop_reply(sig: u8) -> String {
+    format!("S{:02x}", sig)
+}
+
+fn hex(b: &[u8]) -> String {
+    b.iter().map(|b| format!("{:02x}", b)).collect()
+}
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+struct Connection<S> {
+    stream: S
+}
+impl<S: Read + Write> Connection<S> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut b = [0];
+        match self.stream.read(&mut b)? {
+            0 => Ok(None),
+            _ => Ok(Some(b[0]))
+        }
+    }
+    /// read the next packet, acknowledging it. returns None at the end of the stream
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // skip acks and anything else outside a packet
+            loop {
+                match self.read_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => {}
+                    None => return Ok(None)
+                }
+            }
+            let mut data = Vec::new();
+            let mut sum = 0u8;
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(b) => {
+                        sum = sum.wrapping_add(b);
+                        data.push(b)
+                    }
+                    None => return Ok(None)
+                }
+            }
+            let mut cs = [0; 2];
+            self.stream.read_exact(&mut cs)?;
+            let cs = std::str::from_utf8(&cs).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+            if cs == Some(sum) {
+                self.stream.write_all(b"+")?;
+                self.stream.flush()?;
+                return Ok(Some(unescape(&data)))
+            }
+            self.stream.write_all(b"-")?;
+            self.stream.flush()?;
+        }
+    }
+    /// send a packet, resending until gdb acknowledges it
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let sum = data.bytes().fold(0u8, |a, b| a.wrapping_add(b));
+        loop {
+            write!(self.stream, "${}#{:02x}", data, sum)?;
+            self.stream.flush()?;
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(())
+            }
+        }
+    }
+}
+
+/// `}` escapes the next byte, xored with 0x20
+fn unescape(data: &[u8]) -> String {
+    let mut out = Vec::new();
+    let mut iter = data.iter();
+    while let Some(&b) = iter.next() {
+        if b == b'}' {
+            if let Some(&e) = iter.next() {
+                out.push(e ^ 0x20)
+            }
+        }
+        else {
+            out.push(b)
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::processor::Processor;
+
+    /// a fake connection, reading a script and collecting what's written
+    struct Script {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>
+    }
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        let sum = data.bytes().fold(0u8, |a, b| a.wrapping_add(b));
+        format!("${}#{:02x}", data, sum)
+    }
+
+    #[test]
+    fn test_session() {
+        // 0300 LIT 05   0302 JSR (-> 0307)   0303 LIT2 ff0f   0306 STA (halt)
+        // 0307 LIT 2a   0309 JMPr2
+        let rom = [0x80, 0x05, 0x0c, 0xa0, 0xff, 0x0f, 0x15, 0x80, 0x2a, 0x6a];
+        let mut dbg = Debugger::new(Processor::new(&rom, Vec::new()).unwrap());
+        let requests = ["qSupported:swbreak+", "?", "Z0,309,1", "c", "g", "m300,3", "M400,2:abcd", "m400,2", "s", "p0", "z0,309,1", "c"];
+        // every reply is acked by the client
+        let input: String = requests.iter().map(|r| packet(r) + "+").collect();
+        let mut script = Script { input: Cursor::new(input.into_bytes()), output: Vec::new() };
+        let status = serve(&mut dbg, &mut script).unwrap();
+        assert_eq!(status, Some(Status::Halted(0x2a)));
+
+        let replies = ["PacketSize=1000;qXfer:features:read+", "S05", "OK", "S05", "0309fefd00", "80050c", "OK", "abcd", "S05", "0303", "OK", "W2a"];
+        let expected: String = replies.iter().map(|r| format!("+{}", packet(r))).collect();
+        assert_eq!(String::from_utf8(script.output).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_oversized_reads() {
+        let mut dbg = Debugger::new(Processor::new(&[], Vec::new()).unwrap());
+        let requests = ["m0,ffffffffffffffff", "m0,1001", "m0,2", "qXfer:features:read:target.xml:0,ffffffffffffffff", "qXfer:features:read:target.xml:ffff,1000", "qXfer:features:read:target.xml:0,1001"];
+        let input: String = requests.iter().map(|r| packet(r) + "+").collect();
+        let mut script = Script { input: Cursor::new(input.into_bytes()), output: Vec::new() };
+        assert_eq!(serve(&mut dbg, &mut script).unwrap(), None);
+
+        let replies = ["E01", "E01", "0000", "E01", "l", "E01"];
+        let expected: String = replies.iter().map(|r| format!("+{}", packet(r))).collect();
+        assert_eq!(String::from_utf8(script.output).unwrap(), expected);
+    }
+}
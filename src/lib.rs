@@ -0,0 +1,28 @@
+//! avc2, the reference implementation of the AVC2 virtual cpu.
+//!
+//! the main entry point is [`Processor`], which is built from a rom image (without the
+//! `41 56 43 00` header) and a list of devices. custom devices can be written by
+//! implementing [`Device`] and attaching them with [`Processor::attach_device`].
+
+mod utils;
+mod memory;
+mod processor;
+mod dev;
+mod snapshot;
+mod debugger;
+mod gdbstub;
+mod trace;
+mod symbols;
+pub mod opcodes;
+pub mod disasm;
+pub mod asm;
+
+pub use processor::{Processor, Status, UndefinedPolicy};
+pub use memory::{Mem, DmaRequest};
+pub use dev::{Device, DevSpec, WriteResponse, System, Drive, Timer, InputScript};
+pub use utils::{Avc2Error, Fault, Stack};
+pub use snapshot::StateReader;
+pub use debugger::{Debugger, Stop};
+pub use gdbstub::serve as serve_gdb;
+pub use trace::{Tracer, TraceFormat};
+pub use symbols::Symbols;
@@ -1,39 +1,321 @@
-mod utils;
-mod memory;
-mod processor;
-mod dev;
-
-use processor::Processor;
-use std::fs::read;
-use clap::{Arg, Command};
-use dev::DevSpec;
+use avc2::{asm, disasm, opcodes};
+use avc2::{Processor, Status, UndefinedPolicy, DevSpec, System, InputScript, Debugger, serve_gdb, Tracer, TraceFormat, Symbols, Fault};
+use std::fs::{read, read_to_string, write, File};
+use std::net::TcpListener;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use clap::{Arg, ArgMatches, Command};
 
 fn main() {
     let matches = Command::new("avc2")
         .version("0.1.0")
-        .arg(Arg::new("ROM").required(true).help("the rom file to execute"))
-        .arg(Arg::new("DEVICE")
-            .short('d')
-            .required(false)
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .args(machine_args())
+        .arg(Arg::new("SAVE_STATE")
+            .long("save-state")
             .takes_value(true)
-            .multiple_occurrences(true)
-            .help("a device to add. device formats are detailed in the readme.")
+            .help("where to save a snapshot when the rom asks for one")
+        )
+        .arg(Arg::new("TRACE")
+            .long("trace")
+            .takes_value(true)
+            .help("log every instruction executed to a file")
+        )
+        .arg(Arg::new("TRACE_FORMAT")
+            .long("trace-format")
+            .takes_value(true)
+            .possible_values(["text", "binary"])
+            .default_value("text")
+            .help("the format of the --trace log")
+        )
+        .arg(Arg::new("BLOCKS")
+            .long("blocks")
+            .help("run with the block cache, which decodes straight-line code once and reuses it")
+        )
+        .arg(Arg::new("STATS")
+            .long("stats")
+            .help("print how many instructions were executed, and how fast, when the rom stops")
+        )
+        .subcommand(Command::new("debug")
+            .about("run a rom under an interactive debugger")
+            .args(machine_args())
+        )
+        .subcommand(Command::new("gdb")
+            .about("run a rom under a gdb remote protocol server")
+            .args(machine_args())
+            .arg(Arg::new("LISTEN")
+                .long("listen")
+                .takes_value(true)
+                .default_value("1234")
+                .help("a port or address to listen on with tcp, or a path for a unix socket")
+            )
+        )
+        .subcommand(Command::new("disasm")
+            .about("print a disassembly of a rom")
+            .arg(Arg::new("ROM").required(true).help("the rom file to disassemble"))
+        )
+        .subcommand(Command::new("asm")
+            .about("assemble a source file into a rom")
+            .arg(Arg::new("SOURCE").required(true).help("the source file to assemble"))
+            .arg(Arg::new("OUTPUT")
+                .short('o')
+                .takes_value(true)
+                .help("where to write the rom. defaults to the source file with the extension .avcr")
+            )
+            .arg(Arg::new("NO_SYMBOLS")
+                .long("no-symbols")
+                .help("don't write a .sym file next to the rom")
+            )
+        )
+        .subcommand(Command::new("opcodes")
+            .about("print the opcode table (opcode_table.txt)")
         )
         .get_matches()
     ;
+    match matches.subcommand() {
+        Some(("debug", m)) => debug(m),
+        Some(("gdb", m)) => gdb(m),
+        Some(("disasm", m)) => {
+            let rom = read_rom(m.value_of("ROM").unwrap());
+            print!("{}", disasm::listing(&rom, 0x0300))
+        }
+        Some(("opcodes", _)) => print!("{}", opcodes::table_text()),
+        Some(("asm", m)) => {
+            let src_path = m.value_of("SOURCE").unwrap();
+            let (rom, symbols) = asm::assemble_file_with_symbols(src_path).unwrap_or_else(|e| fail(e));
+            let out_path = match m.value_of("OUTPUT") {
+                Some(p) => PathBuf::from(p),
+                None => Path::new(src_path).with_extension("avcr")
+            };
+            if !m.is_present("NO_SYMBOLS") {
+                write(out_path.with_extension("sym"), symbols.to_string()).unwrap_or_else(|e| fail(e))
+            }
+            write(out_path, rom).unwrap_or_else(|e| fail(e))
+        }
+        _ => run(&matches)
+    }
+}
+
+/// options shared by everything that runs a rom
+fn machine_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("ROM").required_unless_present("LOAD_STATE").help("the rom file to execute"),
+        Arg::new("DEVICE")
+            .short('d')
+            .required(false)
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .help("a device to add. device formats are detailed in the readme."),
+        Arg::new("STRICT")
+            .long("strict")
+            .help("fault on stack overflow and underflow instead of wrapping"),
+        Arg::new("UNDEFINED")
+            .long("undefined")
+            .takes_value(true)
+            .possible_values(["nop", "warn", "trap"])
+            .default_value("nop")
+            .help("what to do when the rom executes an undefined instruction"),
+        Arg::new("LOAD_STATE")
+            .long("load-state")
+            .takes_value(true)
+            .help("a snapshot to resume from"),
+        Arg::new("DETERMINISTIC")
+            .long("deterministic")
+            .takes_value(true)
+            .value_name("SEED")
+            .help("seed the rng with SEED, use a virtual clock, and ignore the terminal"),
+        Arg::new("INPUT")
+            .long("input")
+            .alias("replay")
+            .takes_value(true)
+            .help("an input script or recording to use instead of the terminal"),
+        Arg::new("RECORD")
+            .long("record")
+            .takes_value(true)
+            .help("record the input the rom reads to a file"),
+    ]
+}
+
+/// build a processor from the options in `machine_args`.
+/// `no_terminal` stops the system device reading the terminal, for when something else needs it
+fn build_processor(matches: &ArgMatches, no_terminal: bool) -> Processor {
     let devs = if let Some(v) = matches.values_of("DEVICE") {
-        v.map(|d| DevSpec::from_str(d)).collect()
+        v.map(DevSpec::from_str).collect()
     }
     else {
         Ok(Vec::new())
-    }.unwrap();
-    let rom = read(matches.value_of("ROM").unwrap()).unwrap();
+    }.unwrap_or_else(|e| fail(e));
+    let mut p = if let Some(path) = matches.value_of("ROM") {
+        Processor::new(&read_rom(path), devs)
+    }
+    else { // the snapshot has all of memory
+        Processor::new(&[], devs)
+    }.unwrap_or_else(|e| fail(e));
+    if no_terminal || ["DETERMINISTIC", "INPUT", "RECORD"].iter().any(|a| matches.is_present(a)) {
+        let script = matches.value_of("INPUT").map(|path| {
+            let script = read_to_string(path).unwrap_or_else(|e| fail(e));
+            InputScript::parse(&script).unwrap_or_else(|e| fail(e))
+        });
+        // replaying a recording uses its seed, unless told otherwise
+        let seed = if matches.is_present("DETERMINISTIC") {
+            Some(matches.value_of_t("DETERMINISTIC").unwrap_or_else(|e| e.exit()))
+        }
+        else {
+            script.as_ref().and_then(|s| s.seed())
+        };
+        let mut system = match seed {
+            Some(seed) => System::deterministic(seed),
+            None => System::new()
+        };
+        match script {
+            Some(script) => system.set_input_script(script),
+            None if no_terminal => system.set_input_script(InputScript::new()),
+            None => {}
+        }
+        if let Some(path) = matches.value_of("RECORD") {
+            system.record_to(Box::new(File::create(path).unwrap_or_else(|e| fail(e))))
+        }
+        p.attach_device(0, Box::new(system)).unwrap();
+    }
+    if let Some(path) = matches.value_of("LOAD_STATE") {
+        let snapshot = read(path).unwrap_or_else(|e| fail(e));
+        p.load_snapshot(&snapshot).unwrap_or_else(|e| fail(e));
+    }
+    p.set_strict(matches.is_present("STRICT"));
+    p.set_undefined_policy(matches.value_of_t::<UndefinedPolicy>("UNDEFINED").unwrap_or_else(|e| fail(e)));
+    p
+}
+
+/// the symbols in the .sym file next to the rom, if there is one
+fn load_symbols(matches: &ArgMatches) -> Symbols {
+    let path = match matches.value_of("ROM") {
+        Some(rom) => Path::new(rom).with_extension("sym"),
+        None => return Symbols::new()
+    };
+    match read_to_string(&path) {
+        Ok(s) => Symbols::parse(&s).unwrap_or_else(|e| {
+            eprintln!("avc2: ignoring {}: {}", path.display(), e);
+            Symbols::new()
+        }),
+        Err(_) => Symbols::new()
+    }
+}
+
+fn report_fault(fault: Fault, pc: u16, instr: u8, symbols: &Symbols) -> ! {
+    match symbols.describe(pc) {
+        Some(place) => eprintln!("fault at {:04x} {} (instruction {:02x}): {}", pc, place, instr, fault),
+        None => eprintln!("fault at {:04x} (instruction {:02x}): {}", pc, instr, fault)
+    }
+    std::process::exit(255)
+}
 
-    if &rom[..4] != &[0x41, 0x56, 0x43, 0x00] {
-        panic!("bad signature!")
+fn run(matches: &ArgMatches) {
+    let mut p = build_processor(matches, false);
+    let symbols = load_symbols(matches);
+    let save_path = matches.value_of("SAVE_STATE");
+    let mut tracer = matches.value_of("TRACE").map(|path| {
+        let file = BufWriter::new(File::create(path).unwrap_or_else(|e| fail(e)));
+        let mut t = Tracer::new(Box::new(file), matches.value_of_t::<TraceFormat>("TRACE_FORMAT").unwrap_or_else(|e| fail(e)));
+        t.set_symbols(symbols.clone());
+        t
+    });
+    p.set_block_cache(matches.is_present("BLOCKS"));
+    let start = Instant::now();
+    let mut count: u64 = 0;
+    let status = loop {
+        // tracing needs every instruction, so it runs them one at a time
+        let (status, ran) = match &mut tracer {
+            Some(t) => {
+                t.record(&p).unwrap_or_else(|e| fail(e));
+                (p.execute_once(), 1)
+            }
+            None => p.execute_block()
+        };
+        count += ran as u64;
+        if p.take_snapshot_request() {
+            match save_path {
+                Some(path) => write(path, p.save_snapshot()).unwrap_or_else(|e| fail(e)),
+                None => eprintln!("avc2: the rom asked for a snapshot, but no --save-state file was given")
+            }
+        }
+        if status != Status::Running {
+            break status
+        }
+    };
+    let secs = start.elapsed().as_secs_f64();
+    if let Some(t) = &mut tracer {
+        t.flush().unwrap_or_else(|e| fail(e))
     }
-    let mut p = Processor::new(&rom[4..], devs).unwrap();
-    loop {
-        p.execute_once()
+    p.shutdown();
+    println!();
+    if matches.is_present("STATS") {
+        eprintln!("avc2: {} instructions in {:.3}s, {:.1} MIPS", count, secs, count as f64 / secs / 1e6)
     }
+    match status {
+        Status::Halted(ecode) => std::process::exit(ecode as i32),
+        Status::Faulted{fault, pc, instr} => report_fault(fault, pc, instr, &symbols),
+        Status::Running => unreachable!()
+    }
+}
+
+fn debug(matches: &ArgMatches) {
+    // the debugger owns the terminal, so the rom only gets input from a script
+    let mut d = Debugger::new(build_processor(matches, true));
+    d.set_symbols(load_symbols(matches));
+    let stdin = std::io::stdin();
+    d.repl(stdin.lock(), std::io::stdout()).unwrap_or_else(|e| fail(e));
+    d.into_processor().shutdown();
+}
+
+fn gdb(matches: &ArgMatches) {
+    let mut d = Debugger::new(build_processor(matches, true));
+    let listen = matches.value_of("LISTEN").unwrap();
+    // a bare port listens on localhost only
+    let addr = if listen.parse::<u16>().is_ok() { format!("127.0.0.1:{}", listen) } else { listen.to_string() };
+    let status = if addr.contains(':') {
+        let listener = TcpListener::bind(&addr).unwrap_or_else(|e| fail(e));
+        eprintln!("avc2: waiting for gdb on {}", addr);
+        let (stream, _) = listener.accept().unwrap_or_else(|e| fail(e));
+        serve_gdb(&mut d, stream)
+    }
+    else {
+        serve_unix(&mut d, &addr)
+    }.unwrap_or_else(|e| fail(e));
+    let mut p = d.into_processor();
+    p.shutdown();
+    match status {
+        Some(Status::Halted(ecode)) => std::process::exit(ecode as i32),
+        Some(Status::Faulted{fault, pc, instr}) => report_fault(fault, pc, instr, &load_symbols(matches)),
+        _ => {}
+    }
+}
+
+#[cfg(unix)]
+fn serve_unix(d: &mut Debugger, path: &str) -> std::io::Result<Option<Status>> {
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    eprintln!("avc2: waiting for gdb on {}", path);
+    let (stream, _) = listener.accept()?;
+    let status = serve_gdb(d, stream);
+    let _ = std::fs::remove_file(path);
+    status
+}
+#[cfg(not(unix))]
+fn serve_unix(_: &mut Debugger, _: &str) -> std::io::Result<Option<Status>> {
+    fail("unix sockets aren't supported on this platform")
+}
+
+/// read a rom file, without its header
+fn read_rom(path: &str) -> Vec<u8> {
+    let rom = read(path).unwrap_or_else(|e| fail(e));
+    match asm::strip_header(&rom) {
+        Some(code) => code.to_vec(),
+        None => fail(format!("{} is not an avc2 rom (bad signature)", path))
+    }
+}
+
+fn fail(e: impl std::fmt::Display) -> ! {
+    eprintln!("avc2: {}", e);
+    std::process::exit(1)
 }
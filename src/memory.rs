@@ -1,60 +1,155 @@
 use wrapping_arithmetic::wrappit;
 
-use crate::dev::{DevicePage, DevSpec};
-use crate::utils::Avc2Error;
+use crate::dev::{DevicePage, DevSpec, Device};
+use crate::utils::{Avc2Error, Fault};
 
-const MEM_SIZE: u16 = 0xFF00;
+pub(crate) const MEM_SIZE: u16 = 0xFF00;
+pub(crate) const ROM_START: usize = 0x0300;
+pub(crate) const MAX_ROM_SIZE: usize = MEM_SIZE as usize - ROM_START;
 
 pub struct Mem {
     main: [u8; MEM_SIZE as usize],
-    devices: DevicePage
+    devices: DevicePage,
+    // for the block cache (see processor/blocks.rs)
+    /// which bytes are part of a cached block
+    code: Vec<bool>,
+    /// set when cached code is written to
+    code_written: bool,
+    /// set when the device page is read or written
+    device_touched: bool,
+    /// instructions the devices haven't been ticked for yet. they catch up before anything can
+    /// see the difference
+    pending_ticks: u32
 }
 
 impl Mem {
     /// program start is 0x0300
     pub fn new_from_rom(rom: &[u8], devs: Vec<DevSpec>) -> Result<Mem, Avc2Error> {
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(Fault::RomTooLarge(rom.len()).into())
+        }
         let mut main = [0; MEM_SIZE as usize];
         for (i, byte) in rom.iter().enumerate() {
-            main[i + 0x0300] = *byte
+            main[i + ROM_START] = *byte
         }
         Ok(Mem {
             main,
-            devices: DevicePage::new(devs)?
+            devices: DevicePage::new(devs)?,
+            code: vec![false; MEM_SIZE as usize],
+            code_written: false,
+            device_touched: false,
+            pending_ticks: 0
         })
     }
 
+    /// read a byte without touching the device page. device ports read as 0
+    pub fn peek(&self, idx: u16) -> u8 {
+        if idx < MEM_SIZE {
+            self.main[idx as usize]
+        }
+        else {
+            0
+        }
+    }
+    pub fn main(&self) -> &[u8] {
+        &self.main
+    }
+    /// any of the bytes could change, so this drops the block cache
+    pub fn main_mut(&mut self) -> &mut [u8] {
+        self.code_written = true;
+        &mut self.main
+    }
+    /// a byte of the stack pages, which never hold cached code
+    pub(crate) fn stack_mut(&mut self, idx: u16) -> &mut u8 {
+        &mut self.main[idx as usize]
+    }
+    pub fn device_states(&self) -> Vec<Option<(u8, Vec<u8>)>> {
+        self.devices.states()
+    }
+    pub fn load_device_states(&mut self, states: &[Option<(u8, &[u8])>]) -> Result<(), Avc2Error> {
+        self.devices.load_states(states)
+    }
+    pub fn take_snapshot_request(&mut self) -> bool {
+        self.devices.take_snapshot_request()
+    }
+    pub fn tick(&mut self) {
+        self.devices.tick()
+    }
+    /// tick later, the next time the devices are used
+    pub(crate) fn defer_tick(&mut self) {
+        self.pending_ticks += 1
+    }
+    pub(crate) fn catch_up(&mut self) {
+        let n = std::mem::take(&mut self.pending_ticks);
+        if n > 0 {
+            self.devices.tick_n(n)
+        }
+    }
+    pub(crate) fn mark_code(&mut self, range: std::ops::Range<usize>) {
+        self.code[range].fill(true)
+    }
+    pub(crate) fn unmark_code(&mut self, range: std::ops::Range<usize>) {
+        self.code[range].fill(false)
+    }
+    pub(crate) fn code_written(&self) -> bool {
+        self.code_written
+    }
+    pub(crate) fn take_code_written(&mut self) -> bool {
+        std::mem::take(&mut self.code_written)
+    }
+    pub(crate) fn take_device_touched(&mut self) -> bool {
+        std::mem::take(&mut self.device_touched)
+    }
+    pub fn pending_irq(&self) -> Option<u8> {
+        self.devices.pending_irq()
+    }
+    pub fn ack_irq(&mut self, dev_idx: u8) {
+        self.devices.ack_irq(dev_idx)
+    }
+    pub fn halt_code(&self) -> Option<u8> {
+        self.devices.halt_code()
+    }
+    pub fn shutdown(&mut self) {
+        self.devices.shutdown()
+    }
+    pub fn attach_device(&mut self, slot: usize, dev: Box<dyn Device>) -> Result<(), Avc2Error> {
+        self.devices.attach(slot, dev)
+    }
+
     pub fn get(&mut self, idx: u16) -> u8 {
         if idx < MEM_SIZE {
             self.main[idx as usize]
         }
         else { // devices
+            self.catch_up();
+            self.device_touched = true;
             self.devices.read(idx as u8)
         }
     }
-    #[wrappit]
-    pub fn set(&mut self, idx: u16, val: u8) {
+    pub fn set(&mut self, idx: u16, val: u8) -> Result<(), Fault> {
         //eprintln!("CELL {:04x} SET TO {:02x}\r", idx, val);
         if idx < MEM_SIZE {
-            self.main[idx as usize] = val
+            self.main[idx as usize] = val;
+            self.code_written |= self.code[idx as usize]
         }
         else { // devices
+            self.catch_up();
+            self.device_touched = true;
             match self.devices.write(idx as u8, val) {
                 Some(DmaRequest::ToDev{addr, len}) => {
-                    let mut ret = Vec::new();
-                    for i in 0..len {
-                        ret.push(self.get(i + addr))
-                    }
-                    self.devices.dma_callback(ret)
+                    let range = dma_range(addr, len as usize)?;
+                    self.devices.dma_callback(self.main[range].to_vec())?
                 }
                 Some(DmaRequest::ToMem{addr, data}) => {
                     //eprintln!("DMACTL TOMEM\r");
-                    for (i, b) in data.iter().enumerate() {
-                        self.set((i as u16)+ addr, *b)
-                    }
+                    let range = dma_range(addr, data.len())?;
+                    self.code_written |= self.code[range.clone()].contains(&true);
+                    self.main[range].copy_from_slice(&data)
                 }
                 _ => {}
             }
         }
+        Ok(())
     }
 
     #[wrappit]
@@ -64,13 +159,22 @@ impl Mem {
         u16::from_be_bytes([hb, lb])
     }
     #[wrappit]
-    pub fn set_16(&mut self, idx: u16, val: u16) {
+    pub fn set_16(&mut self, idx: u16, val: u16) -> Result<(), Fault> {
         let [hb, lb] = val.to_be_bytes();
-        self.set(idx, hb);
+        self.set(idx, hb)?;
         self.set(idx + 1, lb)
     }
 }
 
+/// dma can't touch the device page, or a device could end up writing to itself
+fn dma_range(addr: u16, len: usize) -> Result<std::ops::Range<usize>, Fault> {
+    let start = addr as usize;
+    if start + len > MEM_SIZE as usize {
+        return Err(Fault::BadDeviceAccess(format!("dma of {} bytes at {:04x} overlaps the device page", len, addr)))
+    }
+    Ok(start..start + len)
+}
+
 pub enum DmaRequest {
     ToMem{addr: u16, data: Vec<u8>},
     ToDev{addr: u16, len: u16}
@@ -0,0 +1,255 @@
+//! everything there is to know about each instruction byte
+//!
+//! [`info`] looks up a byte in a table built from the `kr2ooooo` layout, the same way
+//! `Processor::execute` decodes it. the assembler, disassembler, tracer and undefined
+//! instruction check are all built on it, and opcode_table.txt is generated from it
+//! (see [`table_text`])
+
+use std::sync::OnceLock;
+
+const OPS: [&str; 32] = [
+    // stack/misc
+    "",    "",    "",    "POP", "SWP", "ROT", "DUP", "OVR",
+    // logic/jumps
+    "EQU", "GTH", "JMP", "JNZ", "JSR", "STH", "",    "",
+    // mem
+    "LDZ", "STZ", "LDR", "STR", "LDA", "STA", "PIC", "PUT",
+    // maths
+    "ADC", "SBC", "MUL", "DVM", "AND", "IOR", "XOR", "SFT"
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    /// LIT, with an inline operand
+    Literal,
+    /// the no-op, SEC, CLC, EXT and RTI
+    Special,
+    /// POP, SWP, ROT, DUP, OVR, STH, PIC and PUT
+    Stack,
+    /// EQU and GTH
+    Compare,
+    /// JMP, JNZ and JSR
+    Jump,
+    /// LDZ, STZ, LDR, STR, LDA and STA
+    Memory,
+    /// ADC, SBC, MUL, DVM, AND, IOR, XOR and SFT
+    Arithmetic,
+    /// the opcode slots with nothing in them, which do nothing
+    Unused
+}
+
+/// how many bytes an instruction reads from a stack, and how many it pushes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StackEffect {
+    pub inputs: u8,
+    pub outputs: u8
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpInfo {
+    pub byte: u8,
+    /// the name in opcode_table.txt. undefined bytes and 0x00 have none
+    pub mnemonic: Option<String>,
+    /// whether the spec gives this byte a meaning. 0x00 (the no-op) counts as defined
+    pub defined: bool,
+    pub category: Category,
+    /// how many bytes of operand follow the instruction in memory
+    pub operand: u8,
+    /// whether the instruction takes a signed offset from its own address (relative jumps,
+    /// LDR and STR)
+    pub relative: bool,
+    /// whether the inputs are left on the stack (keep mode). undefined keep mode stack
+    /// primitives run as if the keep bit wasn't set
+    pub keep: bool,
+    /// the effect on the working stack
+    pub working: StackEffect,
+    /// the effect on the return stack
+    pub ret: StackEffect
+}
+
+impl OpInfo {
+    /// how much the instruction grows (or with a negative number, shrinks) a stack
+    pub fn net(&self, is_rst: bool) -> i16 {
+        let e = if is_rst { self.ret } else { self.working };
+        let popped = if self.keep { 0 } else { e.inputs as i16 };
+        e.outputs as i16 - popped
+    }
+}
+
+/// the metadata for a byte
+pub fn info(instr: u8) -> &'static OpInfo {
+    static TABLE: OnceLock<Vec<OpInfo>> = OnceLock::new();
+    &TABLE.get_or_init(|| (0..=0xff).map(build).collect())[instr as usize]
+}
+
+fn build(instr: u8) -> OpInfo {
+    let k = instr & 0x80 != 0;
+    let r = instr & 0x40 != 0;
+    let d = instr & 0x20 != 0;
+    let op = instr & 0x1f;
+    let w = if d { 2 } else { 1 }; // the width of a value
+    let effect = |inputs, outputs| StackEffect { inputs, outputs };
+    let none = StackEffect::default();
+
+    // (category, defined, name, operand, relative, effect on the r stack, effect on the other)
+    let (category, defined, name, operand, relative, this, other) = match op {
+        0 if k => (Category::Literal, true, Some(String::from("LIT")), w, false, effect(0, w), none),
+        0 => {
+            let name = match instr {
+                0x20 => Some("SEC"),
+                0x40 => Some("CLC"),
+                0x60 => Some("EXT"),
+                _ => None
+            };
+            let (this, other) = if instr == 0x60 { (none, effect(0, 1)) } else { (none, none) }; // EXT pushes 0 onto the working stack
+            (Category::Special, true, name.map(String::from), 0, false, this, other)
+        }
+        1 | 2 | 0xe | 0xf => (Category::Unused, false, None, 0, false, none, none),
+        3 if instr == 0x83 => { // RTI pops st, then the return address
+            (Category::Special, true, Some(String::from("RTI")), 0, false, effect(1, 0), effect(2, 0))
+        }
+        3..=7 => {
+            let this = match op {
+                3 => effect(w, 0), // POP
+                4 => effect(2 * w, 2 * w), // SWP
+                5 => effect(3 * w, 3 * w), // ROT
+                6 => effect(w, 2 * w), // DUP
+                _ => effect(2 * w, 3 * w) // OVR
+            };
+            (Category::Stack, !k, None, 0, false, this, none)
+        }
+        8 | 9 => (Category::Compare, true, None, 0, false, effect(2 * w, 1), none),
+        0xa..=0xc => {
+            let addr = if d { 2 } else { 1 };
+            let this = match op {
+                0xb => effect(addr + 1, 0), // JNZ takes a condition too
+                _ => effect(addr, 0)
+            };
+            let other = if op == 0xc { effect(0, 2) } else { none }; // JSR pushes the return address
+            (Category::Jump, true, None, 0, !d, this, other)
+        }
+        0xd => (Category::Stack, true, None, 0, false, effect(w, 0), effect(0, w)), // STH
+        0x10..=0x15 => {
+            let addr = if op >= 0x14 { 2 } else { 1 };
+            let this = if op & 1 == 0 { effect(addr, w) } else { effect(addr + w, 0) };
+            (Category::Memory, true, None, 0, op == 0x12 || op == 0x13, this, none)
+        }
+        0x16 => (Category::Stack, true, None, 0, false, effect(1, w), none), // PIC
+        0x17 => (Category::Stack, true, None, 0, false, effect(1 + w, 0), none), // PUT
+        0x1b => (Category::Arithmetic, true, None, 0, false, effect(2 * w, 2 * w), none), // DVM
+        0x1f => (Category::Arithmetic, true, None, 0, false, effect(w + 1, w), none), // SFT
+        _ => (Category::Arithmetic, true, None, 0, false, effect(2 * w, w), none)
+    };
+
+    let mnemonic = if !defined || instr == 0 {
+        None
+    }
+    else if let Some(name) = name {
+        // the special bytes are named as they are, and LIT's keep bit is part of the name
+        if category == Category::Special { Some(name) } else { Some(with_modes(&name, false, instr)) }
+    }
+    else {
+        Some(with_modes(OPS[op as usize], k, instr))
+    };
+    let (working, ret) = if r { (other, this) } else { (this, other) };
+    OpInfo {
+        byte: instr,
+        mnemonic,
+        defined,
+        category,
+        operand,
+        relative,
+        keep: k && defined && category != Category::Literal && category != Category::Special,
+        working,
+        ret
+    }
+}
+
+/// whether the spec gives this byte a meaning. 0x00 (the no-op) counts as defined
+pub fn is_defined(instr: u8) -> bool {
+    info(instr).defined
+}
+
+/// the mnemonic for a byte, as it appears in opcode_table.txt. undefined bytes and 0x00 have none
+pub fn mnemonic(instr: u8) -> Option<String> {
+    info(instr).mnemonic.clone()
+}
+
+/// a readable name for any byte. undefined bytes get the name their mode bits would give them,
+/// or `.x(..)` if the opcode has no name at all
+pub fn describe(instr: u8) -> String {
+    if let Some(m) = mnemonic(instr) {
+        return m
+    }
+    let name = OPS[(instr & 0x1f) as usize];
+    if instr == 0 || name.is_empty() {
+        format!(".x({:02x})", instr)
+    }
+    else {
+        with_modes(name, instr & 0x80 != 0, instr)
+    }
+}
+
+/// the contents of opcode_table.txt: every mnemonic, with the high nibble of its byte down the
+/// side and the low nibble along the top
+pub fn table_text() -> String {
+    let mut s = String::from("  ");
+    for i in 0..16 {
+        s.push_str(&format!("{:<8x}", i))
+    }
+    s.push('\n');
+    for row in 0..16 {
+        s.push_str(&format!("{:x} ", row));
+        for col in 0..16 {
+            s.push_str(&format!("{:<8}", mnemonic(row << 4 | col).unwrap_or_default()))
+        }
+        s.push('\n')
+    }
+    s
+}
+
+fn with_modes(name: &str, k: bool, instr: u8) -> String {
+    let mut s = String::from(name);
+    if k { s.push('k') }
+    if instr & 0x40 != 0 { s.push('r') }
+    if instr & 0x20 != 0 { s.push('2') }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::Processor;
+
+    #[test]
+    fn test_table_text() {
+        assert_eq!(table_text(), include_str!("../opcode_table.txt"));
+    }
+
+    /// run every byte with full stacks, and check the stack pointers move the way the table says
+    #[test]
+    fn test_stack_effects() {
+        for instr in 0..=0xff {
+            let mut p = Processor::new(&[instr, 0x01, 0x01], Vec::new()).unwrap();
+            for page in [0x0100, 0x0200] {
+                p.mem_mut().main_mut()[page + 0xf0..page + 0x100].fill(0x01);
+            }
+            p.set_registers(0xef, 0xef, 0, 0x0300);
+            p.execute_once();
+            let i = info(instr);
+            assert_eq!(p.wsp() as i16, 0xef - i.net(false), "working stack after {:02x}", instr);
+            assert_eq!(p.rsp() as i16, 0xef - i.net(true), "return stack after {:02x}", instr);
+        }
+    }
+
+    #[test]
+    fn test_info() {
+        let lit = info(0xe0);
+        assert_eq!((lit.mnemonic.as_deref(), lit.operand, lit.ret.outputs), (Some("LITr2"), 2, 2));
+        let jsr = info(0x0c);
+        assert!(jsr.relative && jsr.category == Category::Jump);
+        assert_eq!((jsr.working.inputs, jsr.ret.outputs), (1, 2));
+        assert!(!info(0x86).defined && !info(0x86).keep);
+        assert!(info(0x98).keep);
+    }
+}
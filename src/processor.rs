@@ -1,20 +1,65 @@
 use wrapping_arithmetic::wrappit;
 
 use crate::memory::Mem;
-use crate::utils::Avc2Error;
-use crate::dev::DevSpec;
+use crate::utils::{Avc2Error, Fault, Stack};
+use crate::dev::{DevSpec, Device};
+use crate::opcodes;
+use std::str::FromStr;
+
+mod dispatch;
+mod blocks;
 
 const WST_START: u16 = 0x0100;
 const RST_START: u16 = 0x0200;
+/// interrupt vectors, 2 bytes per device slot
+const IVT_START: u16 = 0x00e0;
+
+const ST_CARRY: u8 = 0b001;
+const ST_IE: u8 = 0b100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    /// a device halted the cpu with an exit code
+    Halted(u8),
+    /// the instruction `instr` at `pc` caused a fault. the pc is left pointing at it
+    Faulted{fault: Fault, pc: u16, instr: u8}
+}
+
+/// what to do with bytes the spec leaves undefined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UndefinedPolicy {
+    /// run them the way avc2 always has (mostly as no-ops, or ignoring the keep bit)
+    #[default]
+    Nop,
+    /// as Nop, but print a warning to stderr
+    Warn,
+    /// fault
+    Trap
+}
+impl FromStr for UndefinedPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nop" => Ok(UndefinedPolicy::Nop),
+            "warn" => Ok(UndefinedPolicy::Warn),
+            "trap" => Ok(UndefinedPolicy::Trap),
+            _ => Err(format!("unknown undefined instruction policy {} (expected nop, warn or trap)", s))
+        }
+    }
+}
 
 pub struct Processor {
     mem: Mem,
     wsp: u8,
     rsp: u8,
     // 7 6 5 4 3 2 1 0
-    //             o c
+    //           i o c
     st: u8,
     pc: u16,
+    strict: bool,
+    undefined: UndefinedPolicy,
+    blocks: Option<blocks::BlockCache>,
 }
 
 impl Processor {
@@ -24,456 +69,142 @@ impl Processor {
             mem,
             wsp: 0xff, rsp: 0xff, st: 0,
             pc: 0x0300,
+            strict: false,
+            undefined: UndefinedPolicy::Nop,
+            blocks: None,
         })
     }
 
-    pub fn execute_once(&mut self) {
-        let instr = self.mem.get(self.pc);
-        self.execute(instr)
+    /// attach a custom device to one of the device slots (1 to 15). slot 0 can only be
+    /// replaced with another system device. a device already in the slot is shut down
+    pub fn attach_device(&mut self, slot: usize, dev: Box<dyn Device>) -> Result<(), Avc2Error> {
+        self.mem.attach_device(slot, dev)
     }
 
-    #[wrappit]
-    fn execute(&mut self, instr: u8) {
-        //eprintln!("PC AT {:04x}\r", self.pc);
-        //eprintln!("EXEC {:02x}\r", instr);
-        //eprintln!("WSP AT {:04x}", (self.wsp as u16) + WST_START);
-        //println!("RSP AT {:04x}", self.rsp);
-        let k = instr & 0x80 != 0; // keep
-        let r = instr & 0x40 != 0; // return stack
-        let d = instr & 0x20 != 0; // double width
-        let op = instr & 0b11111;
-
-        if instr == 0xef {
-            panic!("emergency debug exit")
+    pub fn execute_once(&mut self) -> Status {
+        if let Some(ecode) = self.mem.halt_code() {
+            return Status::Halted(ecode)
         }
-
-        match op { // instruction decode
-            0 => { // lit and extras
-                if k { // LIT
-                    self.pc += 1;
-                    let v = self.mem.get_16(self.pc); // get a 16, and only push lb if in 16 bit mode
-                    let [hb, lb] = v.to_be_bytes();
-                    if d {
-                        //eprintln!("LIT2 {:04x}", v);
-                        self.push(lb, r); // lb
-                        self.pc += 1
-                    }
-                    else {
-                        //eprintln!("LIT {:02x}", hb)
-                    }
-                    self.push(hb, r)
-                }
-                else {
-                    match instr {
-                        0x20 => self.st |= 1, // SEC
-                        0x40 => self.st &= !1, // CLC
-                        0x60 => self.push(0, false), // EXT
-                        _ => {} // NOP
-                    }
-                }
-            }
-            1 => {} // nop
-            2 => {}
-            
-            3..=7 | 0xd => { // stack primitives and STH
-                if instr == 0x83 { // RTI
-                    self.st = self.pop(false);
-                    self.pc = self.pop_16(true)
-                }
-                else {
-                    if d {
-                        let c = self.pop_16(r);
-                        let b = self.pop_16(r);
-                        let a = self.pop_16(r);
-                        //eprintln!("{:04x} {:04x} {:04x}", a, b, c);
-                        match op {
-                            3 => { // POP
-                                self.push_16(b, r); self.push_16(a, r);
-                            }
-                            4 => { // SWP
-                                self.push_16(a, r); self.push_16(c, r); self.push_16(b, r);
-                            }
-                            5 => { // ROT
-                                self.push_16(b, r); self.push_16(a, r); self.push_16(c, r);
-                            }
-                            6 => { // DUP
-                                self.push_16(a, r); self.push_16(b, r); self.push_16(c, r); self.push_16(c, r);
-                            }
-                            7 => { // OVR
-                                self.push_16(a, r); self.push_16(b, r); self.push_16(c, r); self.push_16(b, r);
-                            }
-                            0xd => { // STH
-                                self.push_16(a, r); self.push_16(b, r); self.push_16(c, !r);
-                            }
-                            _ => unreachable!()
-                        }
-                    }
-                    else {
-                        let c = self.pop(r);
-                        let b = self.pop(r);
-                        let a = self.pop(r);
-                        match op {
-                            3 => { // POP
-                                self.push(a, r); self.push(b, r);
-                            }
-                            4 => { // SWP
-                                self.push(a, r); self.push(c, r); self.push(b, r);
-                            }
-                            5 => { // ROT
-                                self.push(b, r); self.push(a, r); self.push(c, r);
-                            }
-                            6 => { // DUP
-                                self.push(a, r); self.push(b, r); self.push(c, r); self.push(c, r);
-                            }
-                            7 => { // OVR
-                                self.push(a, r); self.push(b, r); self.push(c, r); self.push(b, r);
-                            }
-                            0xd => { // STH
-                                self.push(a, r); self.push(b, r); self.push(c, !r);
-                            }
-                            _ => unreachable!()
-                        }
-                    }
-                }
-            }
-
-            8 | 9 => { // EQU and GTH
-                if d {
-                    let (a, b) = if k {
-                        let a = self.pick_16(0, r);
-                        let b = self.pick_16(2, r);
-                        (a, b)
-                    }
-                    else {
-                        let a = self.pop_16(r);
-                        let b = self.pop_16(r);
-                        (a, b)
-                    };
-                    if op == 8 {
-                        self.push(((a == b) as u8) * 0xff, r) 
-                    }
-                    else {
-                        self.push(((a as i16 > b as i16) as u8) * 0xff, r) 
-                    }
-                }
-                else {
-                    let (a, b) = if k {
-                        let a = self.pick(0, r);
-                        let b = self.pick(1, r);
-                        (a, b)
-                    }
-                    else {
-                        let a = self.pop(r);
-                        let b = self.pop(r);
-                        (a, b)
-                    };
-                    if op == 8 {
-                        self.push(((a == b) as u8) * 0xff, r) 
-                    }
-                    else {
-                        self.push(((b as i8 > a as i8) as u8) * 0xff, r) 
-                    }
-                }
-            }
-
-            0xa..=0xc => { // jumps
-                if d {
-                    let addr = if k {
-                        self.pick_16(0, r)
-                    }
-                    else {
-                        self.pop_16(r)
-                    };
-                    
-                    let will_jump = match op {
-                        0xb => {
-                            let cond = if k {
-                                self.pick(2, r)
-                            }
-                            else {
-                                self.pop(r)
-                            };
-                            cond != 0 // jump not zero
-                        }
-                        _  => true
-                    };
-                    if op == 0xc {
-                        self.push_16(self.pc + 1, !r)
-                    }
-                    
-                    if will_jump {
-                        self.pc = addr - 1
-                    }
-                }
-                else { // rel jumps
-                    let ofs = if k {
-                        self.pick(0, r)
-                    }
-                    else {
-                        self.pop(r)
-                    };
-                    
-                    let will_jump = match op {
-                        0xb => {
-                            let cond = if k {
-                                self.pick(1, r)
-                            }
-                            else {
-                                self.pop(r)
-                            };
-                            //eprintln!("JNZ {:02x}\r", cond);
-                            cond != 0 // jump not zero
-                        }
-                        _  => true
-                    };
-                    if op == 0xc {
-                        self.push_16(self.pc + 1, !r)
-                    }
-                    
-                    let dest = self.get_pc_offset(ofs); 
-                    if will_jump {
-                        //eprintln!("JUMPING TO {:04x}", dest);
-                        self.pc = dest - 1
-                    }
-                }
+        let pc = self.pc;
+        let instr = self.mem.get(pc);
+        if let Err(fault) = self.execute(instr) {
+            return Status::Faulted{fault, pc, instr}
+        }
+        self.mem.tick();
+        if let Some(ecode) = self.mem.halt_code() {
+            return Status::Halted(ecode)
+        }
+        if let Err(fault) = self.service_interrupts() {
+            return Status::Faulted{fault, pc: self.pc, instr: self.mem.peek(self.pc)}
+        }
+        Status::Running
+    }
+    /// if interrupts are enabled and a device wants one, push the return address and status
+    /// (in the order RTI pops them) and jump to the device's vector
+    fn service_interrupts(&mut self) -> Result<(), Fault> {
+        if self.st & ST_IE == 0 {
+            return Ok(())
+        }
+        if let Some(dev_idx) = self.mem.pending_irq() {
+            self.mem.ack_irq(dev_idx);
+            let vector = self.mem.get_16(IVT_START + dev_idx as u16 * 2);
+            if vector != 0 {
+                // RTI increments the pc after popping it, like any other jump
+                self.push_16(self.pc.wrapping_sub(1), true)?;
+                self.push(self.st, false)?;
+                self.st &= !ST_IE;
+                self.pc = vector
             }
-
-            0x10..=0x15 => { // memory accessing
-                let mut is_abs = false;
-                let addr = if !k {
-                    match op {
-                        0x10..=0x11 => { // zpg
-                            self.pop(r) as u16
-                        }
-                        0x12..=0x13 => { // rel
-                            let ofs = self.pop(r);
-                            self.get_pc_offset(ofs)
-                        }
-                        _ => { // absolute
-                            is_abs = true;
-                            self.pop_16(r)
-                        }
-                    }
-                }
-                else {
-                    match op {
-                        0x10..=0x11 => { // zpg
-                            self.pick(0, r) as u16
-                        }
-                        0x12..=0x13 => { // rel
-                            let ofs = self.pick(0, r);
-                            self.get_pc_offset(ofs)
-                        }
-                        _ => { // absolute
-                            is_abs = true;
-                            self.pick_16(0, r)
-                        }
-                    }
-                };
-                //eprintln!("DIRECT MEM ACCESS AT {:04x}", addr);
-                // all even values are load
-                if op & 0b1 == 0 { // load
-                    let v = self.mem.get_16(addr);
-                    let [hb, lb] = v.to_be_bytes();
-                    if d {
-                        //eprintln!("LOAD {:04x}", v);
-                        self.push(lb, r); // lb
-                        //self.pc += 1
-                    }
-                    else {
-                        //eprintln!("LOAD {:02x}", hb)
-                    }
-                    self.push(hb, r)
-                }
-                else { // store
-                    if d {
-                        let v = if !k {
-                            self.pop_16(r)
-                        }
-                        else {
-                            if is_abs {
-                                self.pick_16(2, r)
-                            }
-                            else {
-                                self.pick_16(1, r)
-                            }
-                        };
-                        self.mem.set_16(addr, v)
-                    }
-                    else {
-                        let v = if !k {
-                            self.pop(r)
-                        }
-                        else {
-                            if is_abs {
-                                self.pick(2, r)
-                            }
-                            else {
-                                self.pick(1, r)
-                            }
-                        };
-                        self.mem.set(addr, v)
-                    }
-                }
+        }
+        Ok(())
+    }
+    /// run until the cpu stops, then return why. this uses the block cache if it's on
+    pub fn run(&mut self) -> Status {
+        loop {
+            let (status, _) = self.execute_block();
+            if status != Status::Running {
+                return status
             }
+        }
+    }
+    /// shut down all devices (saving drives etc). this also happens when the processor is dropped
+    pub fn shutdown(&mut self) {
+        self.mem.shutdown()
+    }
 
-            0x16..=0x17 => { // PIC and PUT
-                let ofs = self.pop(r);
-                if d {
-                    if op == 0x16 { // PIC
-                        let v = self.pick_16(ofs, r);
-                        self.push_16(v, r)
-                    }
-                    else { // PUT
-                        let v = self.pop_16(r);
-                        self.put_16(v, ofs, r)
-                    }
-                }
-                else {
-                    if op == 0x16 { // PIC
-                        let v = self.pick(ofs, r);
-                        self.push(v, r)
-                    }
-                    else { // PUT
-                        let v = self.pop(r);
-                        self.put(v, ofs, r)
-                    }
-                }
-            }
+    /// in strict mode, pushing to a full stack or popping from an empty one is a fault
+    /// instead of wrapping round the stack page
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict
+    }
 
-            0x18..=0x1e => { // arithmetic
-                if d {
-                    let (a, b) = if k {
-                        let a = self.pick_16(0, r);
-                        let b = self.pick_16(2, r);
-                        (a, b)
-                    }
-                    else {
-                        let a = self.pop_16(r);
-                        let b = self.pop_16(r);
-                        (a, b)
-                    };
-                    let x = match op {
-                        0x18 => { // ADC
-                            //eprintln!("ADC2 {:04x} {:04x}\r", a, b);
-                            let c = self.st & 1; // get carry flag
-                            let x = a + b;
-                            if a > x { // test for overflow
-                                self.st |= 1 // set carry
-                            }
-                            else {
-                                self.st &= !1 // clear carry
-                            }
-                            x + c.into() // add carry at the end
-                        }
-                        0x19 => { // SBC
-                            let c = !self.st & 1; // get borrow flag
-                            let x = b - a;
-                            if a < x { // test for underflow
-                                self.st &= !1 // clear carry = set borrow
-                            }
-                            else {
-                                self.st |= 1 // set carry = clear borrow
-                            }
-                            x - c.into()
-                        }
-                        0x1a => a * b, // MUL
-                        0x1b => { // DVM
-                            self.push_16(b / a, r);
-                            b % a
-                        }
-                        0x1c => a & b, // AND
-                        0x1d => a | b, // IOR
-                        0x1e => a ^ b, // XOR
-                        _ => unreachable!()
-                    };
-                    self.push_16(x, r)
-                }
-                else {
-                    let (a, b) = if k {
-                        let a = self.pick(0, r);
-                        let b = self.pick(1, r);
-                        (a, b)
-                    }
-                    else {
-                        let a = self.pop(r);
-                        let b = self.pop(r);
-                        (a, b)
-                    };
-                    let x = match op {
-                        0x18 => { // ADC
-                            let c = self.st & 1; // get carry flag
-                            let x = a + b;
-                            if a > x { // test for overflow
-                                self.st |= 1 // set carry
-                            }
-                            else {
-                                self.st &= !1 // clear carry
-                            }
-                            x + c // add carry at the end
-                        }
-                        0x19 => { // SBC
-                            let c = !self.st & 1; // get borrow flag
-                            let x = b - a;
-                            if a < x { // test for underflow
-                                self.st &= !1 // clear carry = set borrow
-                            }
-                            else {
-                                self.st |= 1 // set carry = clear borrow
-                            }
-                            x - c
-                        }
-                        0x1a => a * b, // MUL
-                        0x1b => { // DVM
-                            self.push(b / a, r);
-                            b % a
-                        }
-                        0x1c => a & b, // AND
-                        0x1d => a | b, // IOR
-                        0x1e => a ^ b, // XOR
-                        _ => unreachable!()
-                    };
-                    self.push(x, r)
-                }
-            }
+    pub fn set_undefined_policy(&mut self, policy: UndefinedPolicy) {
+        self.undefined = policy;
+        self.drop_blocks() // they were decoded under the old policy
+    }
 
-            0x1f => { // SFT
-                let sft_amt = self.pop(r);
-                let ls = (sft_amt & 0xf0) >> 4;
-                let rs = sft_amt & 0x0f;
-                if d {
-                    let mut v = self.pop_16(r);
-                    v <<= ls;
-                    v >>= rs;
-                    self.push_16(v, r)
-                }
-                else {
-                    let mut v = self.pop(r);
-                    v <<= ls;
-                    v >>= rs;
-                    self.push(v, r)
-                }
-            }
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc
+    }
+    pub fn wsp(&self) -> u8 {
+        self.wsp
+    }
+    pub fn rsp(&self) -> u8 {
+        self.rsp
+    }
+    pub fn st(&self) -> u8 {
+        self.st
+    }
+    /// set every register at once, e.g. to restore a saved machine
+    pub fn set_registers(&mut self, wsp: u8, rsp: u8, st: u8, pc: u16) {
+        self.wsp = wsp;
+        self.rsp = rsp;
+        self.st = st;
+        self.pc = pc
+    }
+    /// whether a device has asked for a snapshot since the last call
+    pub fn take_snapshot_request(&mut self) -> bool {
+        self.mem.take_snapshot_request()
+    }
+    pub fn mem(&self) -> &Mem {
+        &self.mem
+    }
+    pub fn mem_mut(&mut self) -> &mut Mem {
+        &mut self.mem
+    }
+    /// the live contents of the working stack, bottom first
+    pub fn working_stack(&self) -> Vec<u8> {
+        self.stack_contents(self.wsp, WST_START)
+    }
+    /// the live contents of the return stack, bottom first
+    pub fn return_stack(&self) -> Vec<u8> {
+        self.stack_contents(self.rsp, RST_START)
+    }
+    fn stack_contents(&self, sp: u8, start: u16) -> Vec<u8> {
+        (sp as u16 + 1..0x100).rev().map(|i| self.mem.peek(start + i)).collect()
+    }
 
-            _ => {} // nop
+    fn execute(&mut self, instr: u8) -> Result<(), Fault> {
+        if instr == 0xef {
+            return Err(Fault::DebugBreak)
         }
-
-        /*for i in (self.wsp as u16) + WST_START + 1..WST_START + 0x100 {
-            eprint!("{:02x} ", self.mem.get(i))
+        if self.undefined != UndefinedPolicy::Nop && !opcodes::is_defined(instr) {
+            if self.undefined == UndefinedPolicy::Trap {
+                return Err(Fault::UndefinedInstruction)
+            }
+            eprintln!("warning: undefined instruction {:02x} ({}) at {:04x}", instr, opcodes::describe(instr), self.pc)
         }
-        eprintln!("");*/
-        self.pc += 1;
+        dispatch::TABLE[instr as usize](self)?;
+        self.pc = self.pc.wrapping_add(1);
+        Ok(())
     }
 
+    /// the pc plus a signed offset, wrapping around the ends of memory
     fn get_pc_offset(&self, ofs: u8) -> u16 {
-        let ofs = ofs as i8;
-        let neg = ofs < 0;
-        if neg {
-            self.pc - (ofs * -1) as u16
-        }
-        else {
-            self.pc + ofs as u16
-        }
+        self.pc.wrapping_add(ofs as i8 as u16)
     }
 
     // internal stack manipulation
@@ -481,10 +212,27 @@ impl Processor {
     // when pushing a u16, you push lb first
     // so that when reading it, the endianness is right
     // when popping a u16, pop hb first
+    // the stacks never reach the device page, so they go straight to main memory
+
+    /// how many bytes are on a stack. only meaningful in strict mode, where the pointers never wrap
+    fn depth(&self, is_rst: bool) -> u8 {
+        0xff - if is_rst { self.rsp } else { self.wsp }
+    }
+    fn stack_fault(&self, is_rst: bool, overflow: bool) -> Fault {
+        let stack = if is_rst { Stack::Return } else { Stack::Working };
+        if overflow {
+            Fault::StackOverflow(stack)
+        }
+        else {
+            Fault::StackUnderflow(stack)
+        }
+    }
 
     #[wrappit]
-    fn push(&mut self, val: u8, is_rst: bool) {
-        //println!("PUSHING {:02x}\r", val);
+    fn push(&mut self, val: u8, is_rst: bool) -> Result<(), Fault> {
+        if self.strict && self.depth(is_rst) == 0xff {
+            return Err(self.stack_fault(is_rst, true))
+        }
         let idx = if is_rst {
             let idx = (self.rsp as u16) + RST_START;
             self.rsp -= 1;
@@ -495,10 +243,14 @@ impl Processor {
             self.wsp -= 1;
             idx
         };
-        self.mem.set(idx, val);
+        *self.mem.stack_mut(idx) = val;
+        Ok(())
     }
     #[wrappit]
-    fn pop(&mut self, is_rst: bool) -> u8 {
+    fn pop(&mut self, is_rst: bool) -> Result<u8, Fault> {
+        if self.strict && self.depth(is_rst) == 0 {
+            return Err(self.stack_fault(is_rst, false))
+        }
         let idx = if is_rst {
             self.rsp += 1;
             (self.rsp as u16) + RST_START
@@ -507,53 +259,203 @@ impl Processor {
             self.wsp += 1;
             (self.wsp as u16) + WST_START
         };
-        let val = self.mem.get(idx);
-        //println!("POPPING {:02x}", val);
-        val
+        Ok(self.mem.main()[idx as usize])
     }
-    fn push_16(&mut self, val: u16, is_rst: bool) {
+    fn push_16(&mut self, val: u16, is_rst: bool) -> Result<(), Fault> {
         let [hb, lb] = val.to_be_bytes();
-        self.push(lb, is_rst);
-        self.push(hb, is_rst);
+        self.push(lb, is_rst)?;
+        self.push(hb, is_rst)
     }
-    fn pop_16(&mut self, is_rst: bool) -> u16 {
-        let hb = self.pop(is_rst);
-        let lb = self.pop(is_rst);
-        u16::from_be_bytes([hb, lb])
+    fn pop_16(&mut self, is_rst: bool) -> Result<u16, Fault> {
+        let hb = self.pop(is_rst)?;
+        let lb = self.pop(is_rst)?;
+        Ok(u16::from_be_bytes([hb, lb]))
     }
 
     /// x_ofs ... x_1 x_0 -- val ... x_1 x_0
     #[wrappit]
-    fn put(&mut self, val: u8, ofs: u8, is_rst: bool) {
+    fn put(&mut self, val: u8, ofs: u8, is_rst: bool) -> Result<(), Fault> {
+        if self.strict && ofs >= self.depth(is_rst) {
+            return Err(self.stack_fault(is_rst, false))
+        }
         let idx = if is_rst {
             ((self.rsp + ofs + 1) as u16) + RST_START
         }
         else {
             ((self.wsp + ofs + 1) as u16) + WST_START
         };
-        self.mem.set(idx, val)
+        *self.mem.stack_mut(idx) = val;
+        Ok(())
     }
     #[wrappit]
-    fn pick(&mut self, ofs: u8, is_rst: bool) -> u8 {
+    fn pick(&mut self, ofs: u8, is_rst: bool) -> Result<u8, Fault> {
+        if self.strict && ofs >= self.depth(is_rst) {
+            return Err(self.stack_fault(is_rst, false))
+        }
         let idx = if is_rst {
             ((self.rsp + ofs + 1) as u16) + RST_START
         }
         else {
             ((self.wsp + ofs + 1) as u16) + WST_START
         };
-        self.mem.get(idx)
+        Ok(self.mem.main()[idx as usize])
     }
     /// hb is at ofs, lb is at ofs + 1
     #[wrappit]
-    fn put_16(&mut self, val: u16, ofs: u8, is_rst: bool) {
+    fn put_16(&mut self, val: u16, ofs: u8, is_rst: bool) -> Result<(), Fault> {
         let [hb, lb] = val.to_be_bytes();
-        self.put(hb, ofs, is_rst);
+        self.put(hb, ofs, is_rst)?;
         self.put(lb, ofs + 1, is_rst)
     }
     #[wrappit]
-    fn pick_16(&mut self, ofs: u8, is_rst: bool) -> u16 {
-        let hb = self.pick(ofs, is_rst);
-        let lb = self.pick(ofs + 1, is_rst);
-        u16::from_be_bytes([hb, lb])
+    fn pick_16(&mut self, ofs: u8, is_rst: bool) -> Result<u16, Fault> {
+        let hb = self.pick(ofs, is_rst)?;
+        let lb = self.pick(ofs + 1, is_rst)?;
+        Ok(u16::from_be_bytes([hb, lb]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dev::WriteResponse;
+
+    struct IrqDev {
+        pending: bool
+    }
+    impl Device for IrqDev {
+        fn write(&mut self, _addr: u8, _val: u8) -> WriteResponse {
+            WriteResponse::None
+        }
+        fn read(&mut self, _addr: u8) -> u8 {
+            0
+        }
+        fn devid(&self) -> u8 {
+            0xf0
+        }
+        fn irq(&self) -> bool {
+            self.pending
+        }
+        fn ack_irq(&mut self) {
+            self.pending = false
+        }
+    }
+
+    /// a system device that keeps what's written to stdout
+    struct Capture {
+        out: std::rc::Rc<std::cell::RefCell<Vec<u8>>>
+    }
+    impl Device for Capture {
+        fn write(&mut self, addr: u8, val: u8) -> WriteResponse {
+            match addr {
+                0x9 => self.out.borrow_mut().push(val),
+                0xf => return WriteResponse::Shutdown(val),
+                _ => {}
+            }
+            WriteResponse::None
+        }
+        fn read(&mut self, _addr: u8) -> u8 {
+            0
+        }
+        fn devid(&self) -> u8 {
+            1
+        }
+    }
+
+    /// notes when it's shut down
+    struct Closer {
+        closed: std::rc::Rc<std::cell::Cell<bool>>
+    }
+    impl Device for Closer {
+        fn write(&mut self, _addr: u8, _val: u8) -> WriteResponse {
+            WriteResponse::None
+        }
+        fn read(&mut self, _addr: u8) -> u8 {
+            0
+        }
+        fn shutdown(&mut self) {
+            self.closed.set(true)
+        }
+    }
+
+    #[test]
+    fn test_attach_replaces() {
+        let mut p = Processor::new(&[], Vec::new()).unwrap();
+        let closed = std::rc::Rc::new(std::cell::Cell::new(false));
+        p.attach_device(2, Box::new(Closer { closed: std::rc::Rc::clone(&closed) })).unwrap();
+        assert!(!closed.get());
+        p.attach_device(2, Box::new(IrqDev { pending: false })).unwrap();
+        assert!(closed.get());
+    }
+
+    #[test]
+    fn test_block_cache_self_modifying() {
+        // the bootstrap example reads a block from the drive over its own code, so the blocks
+        // cached from the old code have to be dropped
+        let rom = crate::asm::strip_header(include_bytes!("../examples/bootstrap.avcr")).unwrap();
+        let run = |blocks: bool| {
+            let drive = std::env::temp_dir().join(format!("avc2-bootstrap-{}-{}.avd", std::process::id(), blocks));
+            std::fs::write(&drive, include_bytes!("../examples/bootstrap_test.avd")).unwrap();
+            let mut p = Processor::new(rom, vec![DevSpec::new(1, 2, drive.to_str().unwrap())]).unwrap();
+            let out = Default::default();
+            p.attach_device(0, Box::new(Capture { out: std::rc::Rc::clone(&out) })).unwrap();
+            p.set_block_cache(blocks);
+            // step by hand, so a regression that never halts fails instead of hanging
+            let mut steps = 0;
+            let status = loop {
+                let (status, ran) = p.execute_block();
+                if status != Status::Running {
+                    break status
+                }
+                steps += ran;
+                assert!(steps < 10_000, "blocks {}: didn't halt", blocks);
+            };
+            p.shutdown();
+            std::fs::remove_file(&drive).unwrap();
+            (status, out.take(), p.mem().main().to_vec(), p.working_stack(), p.return_stack())
+        };
+        let plain = run(false);
+        assert!(matches!(plain.0, Status::Halted(_)), "{:?}", plain.0);
+        assert_eq!(plain.1, b"hello world!\n");
+        assert!(run(true) == plain);
+    }
+
+    #[test]
+    fn test_pop2() {
+        // POP2 used to push the two items under the one it dropped back swapped
+        let rom = crate::asm::assemble("LIT2 .w(0x1122) LIT2 .w(0x3344) LIT2 .w(0x5566) POP2").unwrap();
+        let mut p = Processor::new(&rom[4..], Vec::new()).unwrap();
+        for _ in 0..4 {
+            assert_eq!(p.execute_once(), Status::Running);
+        }
+        assert_eq!(p.working_stack(), [0x22, 0x11, 0x44, 0x33]);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let rom = [
+            0xa0, 0x03, 0x20, 0xa0, 0x00, 0xe2, 0x35, // vector 1 = 0x0320
+            0xa0, 0x03, 0x0d, 0x2d, 0x80, ST_IE, 0x83, // enable interrupts with RTI, landing at 0x030e
+            0x80, 0xfe, 0x0a, // spin
+        ];
+        let mut rom = rom.to_vec();
+        rom.resize(0x20, 0);
+        rom.extend([0x80, 0x2a, 0x80, 0x00, 0x11, 0x83]); // handler: store 2a at 0x0000 and return
+
+        let mut p = Processor::new(&rom, Vec::new()).unwrap();
+        p.attach_device(1, Box::new(IrqDev { pending: true })).unwrap();
+        for _ in 0..7 {
+            assert_eq!(p.execute_once(), Status::Running);
+        }
+        assert_eq!(p.pc(), 0x0320);
+        assert_eq!(p.st() & ST_IE, 0);
+        for _ in 0..4 {
+            p.execute_once();
+        }
+        assert_eq!(p.mem().peek(0), 0x2a);
+        assert_eq!(p.pc(), 0x030e);
+        assert_eq!(p.st() & ST_IE, ST_IE);
+        assert!(p.working_stack().is_empty());
+        assert!(p.return_stack().is_empty());
     }
 }
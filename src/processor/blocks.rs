@@ -0,0 +1,158 @@
+//! the block cache, an optional engine that decodes straight-line runs of code once and then
+//! runs them without fetching or decoding each instruction again
+//!
+//! a block ends after a jump or RTI, or before a byte that has to go through `execute`. blocks
+//! are never built on the stack pages, which are written without going through `Mem::set`, or
+//! where they would run into the device page. a block stops early after any instruction that
+//! touches the device page, and the devices are only ticked once it's done, catching up first if
+//! the block uses them. writing to a cached byte drops every block, since self-modifying code is
+//! allowed. blocks only run while interrupts are disabled, so nothing needs servicing part way
+//! through one
+
+use super::*;
+use super::dispatch::{Handler, TABLE};
+use crate::memory::MEM_SIZE;
+
+/// the most instructions in a block
+const MAX_LEN: usize = 64;
+
+struct Entry {
+    handler: Handler,
+    instr: u8
+}
+
+struct Block {
+    entries: Box<[Entry]>,
+    /// one past the last byte
+    end: u16
+}
+
+pub(super) struct BlockCache {
+    /// indexed by the address a block starts at
+    blocks: Vec<Option<Box<Block>>>,
+    /// the start of every cached block, so dropping them doesn't scan all of memory
+    live: Vec<u16>
+}
+
+impl BlockCache {
+    pub(super) fn new() -> BlockCache {
+        BlockCache {
+            blocks: (0..MEM_SIZE).map(|_| None).collect(),
+            live: Vec::new()
+        }
+    }
+}
+
+/// whether an instruction's bytes can be cached
+fn cacheable(addr: u16, len: u16) -> bool {
+    let end = addr as usize + len as usize;
+    end <= MEM_SIZE as usize && !(end > WST_START as usize && addr < RST_START + 0x100)
+}
+
+impl Processor {
+    /// use the block cache in `execute_block`
+    pub fn set_block_cache(&mut self, enabled: bool) {
+        self.drop_blocks();
+        self.blocks = if enabled { Some(BlockCache::new()) } else { None }
+    }
+
+    /// run instructions up to the end of the next block, and return the status and how many
+    /// instructions were executed, not counting one that faulted. this behaves like calling
+    /// `execute_once` that many times. without the block cache, or while interrupts are enabled,
+    /// it runs one instruction
+    pub fn execute_block(&mut self) -> (Status, usize) {
+        if let Some(ecode) = self.mem.halt_code() {
+            return (Status::Halted(ecode), 0)
+        }
+        if self.mem.take_code_written() {
+            self.drop_blocks()
+        }
+        let start = self.pc as usize;
+        let cached = match &mut self.blocks {
+            Some(cache) if self.st & ST_IE == 0 && start < MEM_SIZE as usize => Some(cache.blocks[start].take()),
+            _ => None
+        };
+        let block = match cached {
+            Some(Some(block)) => block,
+            Some(None) => match self.decode(self.pc) {
+                Some(block) => block,
+                None => return self.execute_one()
+            }
+            None => return self.execute_one()
+        };
+
+        let mut ran = 0;
+        let mut fault = None;
+        self.mem.take_device_touched();
+        for entry in block.entries.iter() {
+            let pc = self.pc;
+            if let Err(f) = (entry.handler)(self) {
+                fault = Some(Status::Faulted{fault: f, pc, instr: entry.instr});
+                break
+            }
+            self.pc = self.pc.wrapping_add(1);
+            self.mem.defer_tick();
+            ran += 1;
+            // the rest of the block might be stale, or a device might want something
+            if self.mem.take_device_touched() || self.mem.code_written() {
+                break
+            }
+        }
+        self.mem.catch_up();
+        self.blocks.as_mut().unwrap().blocks[start] = Some(block);
+        if self.mem.take_code_written() {
+            self.drop_blocks()
+        }
+
+        if let Some(fault) = fault {
+            return (fault, ran)
+        }
+        if let Some(ecode) = self.mem.halt_code() {
+            return (Status::Halted(ecode), ran)
+        }
+        if let Err(fault) = self.service_interrupts() {
+            return (Status::Faulted{fault, pc: self.pc, instr: self.mem.peek(self.pc)}, ran)
+        }
+        (Status::Running, ran)
+    }
+
+    fn execute_one(&mut self) -> (Status, usize) {
+        let status = self.execute_once();
+        let ran = if let Status::Faulted{..} = status { 0 } else { 1 };
+        (status, ran)
+    }
+
+    fn decode(&mut self, start: u16) -> Option<Box<Block>> {
+        let mut entries = Vec::new();
+        let mut addr = start;
+        while entries.len() < MAX_LEN {
+            let instr = self.mem.peek(addr);
+            let info = opcodes::info(instr);
+            let len = 1 + info.operand as u16;
+            if !cacheable(addr, len) || instr == 0xef || (self.undefined != UndefinedPolicy::Nop && !info.defined) {
+                break
+            }
+            entries.push(Entry { handler: TABLE[instr as usize], instr });
+            addr += len;
+            if info.category == opcodes::Category::Jump || instr == 0x83 { // RTI
+                break
+            }
+        }
+        if entries.is_empty() {
+            return None
+        }
+        self.mem.mark_code(start as usize..addr as usize);
+        self.blocks.as_mut().unwrap().live.push(start);
+        Some(Box::new(Block { entries: entries.into_boxed_slice(), end: addr }))
+    }
+
+    pub(super) fn drop_blocks(&mut self) {
+        if let Some(cache) = &mut self.blocks {
+            for start in cache.live.drain(..) {
+                if let Some(block) = cache.blocks[start as usize].take() {
+                    self.mem.unmark_code(start as usize..block.end as usize)
+                }
+            }
+        }
+    }
+}
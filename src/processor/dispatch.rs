@@ -0,0 +1,270 @@
+//! the instruction handlers, and the table `Processor::execute` dispatches through
+//!
+//! every opcode has one handler, written once for both widths and specialised at compile time
+//! for each combination of the k, r and 2 bits, so nothing is decoded while running. the table
+//! is laid out like the instruction byte: one row of 32 opcodes for each mode combination
+
+use super::*;
+
+pub(super) type Handler = fn(&mut Processor) -> Result<(), Fault>;
+
+pub(super) static TABLE: [Handler; 256] = {
+    // row index is kr2
+    let rows = [
+        row::<false, false, false>(), row::<false, false, true>(),
+        row::<false, true, false>(), row::<false, true, true>(),
+        row::<true, false, false>(), row::<true, false, true>(),
+        row::<true, true, false>(), row::<true, true, true>(),
+    ];
+    let mut table = [nop as Handler; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = rows[i >> 5][i & 0x1f];
+        i += 1
+    }
+    table
+};
+
+const ZPG: u8 = 0;
+const REL: u8 = 1;
+const ABS: u8 = 2;
+
+const fn row<const K: bool, const R: bool, const D: bool>() -> [Handler; 32] {
+    [
+        special::<K, R, D>, nop, nop,
+        // keep mode is ignored on the stack primitives, which frees 0x83 for RTI
+        if K && !R && !D { rti } else { pop::<R, D> },
+        swp::<R, D>, rot::<R, D>, dup::<R, D>, ovr::<R, D>,
+        compare::<K, R, D, 0x08>, compare::<K, R, D, 0x09>,
+        jump::<K, R, D, 0x0a>, jump::<K, R, D, 0x0b>, jump::<K, R, D, 0x0c>,
+        sth::<K, R, D>, nop, nop,
+        load::<K, R, D, ZPG>, store::<K, R, D, ZPG>,
+        load::<K, R, D, REL>, store::<K, R, D, REL>,
+        load::<K, R, D, ABS>, store::<K, R, D, ABS>,
+        pic::<K, R, D>, put::<K, R, D>,
+        arith::<K, R, D, 0x18>, arith::<K, R, D, 0x19>, arith::<K, R, D, 0x1a>, arith::<K, R, D, 0x1b>,
+        arith::<K, R, D, 0x1c>, arith::<K, R, D, 0x1d>, arith::<K, R, D, 0x1e>,
+        sft::<K, R, D>,
+    ]
+}
+
+// inputs are popped, or in keep mode picked one after another from the top down, so they stay
+// where they are. `ofs` is how far down the next one is
+impl Processor {
+    fn input<const K: bool>(&mut self, r: bool, ofs: &mut u8) -> Result<u8, Fault> {
+        if K {
+            let v = self.pick(*ofs, r)?;
+            *ofs += 1;
+            Ok(v)
+        }
+        else {
+            self.pop(r)
+        }
+    }
+    fn input_w<const K: bool, const D: bool>(&mut self, r: bool, ofs: &mut u8) -> Result<u16, Fault> {
+        let hb = self.input::<K>(r, ofs)?;
+        if D {
+            let lb = self.input::<K>(r, ofs)?;
+            Ok(u16::from_be_bytes([hb, lb]))
+        }
+        else {
+            Ok(hb as u16)
+        }
+    }
+    fn pop_w<const D: bool>(&mut self, r: bool) -> Result<u16, Fault> {
+        if D { self.pop_16(r) } else { self.pop(r).map(u16::from) }
+    }
+    fn push_w<const D: bool>(&mut self, v: u16, r: bool) -> Result<(), Fault> {
+        if D { self.push_16(v, r) } else { self.push(v as u8, r) }
+    }
+    fn address<const K: bool, const R: bool, const MODE: u8>(&mut self, ofs: &mut u8) -> Result<u16, Fault> {
+        Ok(match MODE {
+            ZPG => self.input::<K>(R, ofs)? as u16,
+            REL => {
+                let o = self.input::<K>(R, ofs)?;
+                self.get_pc_offset(o)
+            }
+            _ => self.input_w::<K, true>(R, ofs)?
+        })
+    }
+    fn set_carry(&mut self, carry: bool) {
+        if carry {
+            self.st |= ST_CARRY
+        }
+        else {
+            self.st &= !ST_CARRY
+        }
+    }
+}
+
+fn nop(_: &mut Processor) -> Result<(), Fault> {
+    Ok(())
+}
+
+/// LIT in keep mode, otherwise NOP, SEC, CLC and EXT
+fn special<const K: bool, const R: bool, const D: bool>(p: &mut Processor) -> Result<(), Fault> {
+    if K {
+        let v = if D { p.mem.get_16(p.pc.wrapping_add(1)) } else { p.mem.get(p.pc.wrapping_add(1)) as u16 };
+        p.push_w::<D>(v, R)?;
+        p.pc = p.pc.wrapping_add(if D { 2 } else { 1 })
+    }
+    else {
+        match (R, D) {
+            (false, true) => p.st |= ST_CARRY, // SEC
+            (true, false) => p.st &= !ST_CARRY, // CLC
+            (true, true) => p.push(0, false)?, // EXT
+            _ => {} // NOP
+        }
+    }
+    Ok(())
+}
+
+fn rti(p: &mut Processor) -> Result<(), Fault> {
+    p.st = p.pop(false)?;
+    p.pc = p.pop_16(true)?;
+    Ok(())
+}
+
+// only pop what each primitive needs, so strict mode doesn't see phantom underflows
+
+fn pop<const R: bool, const D: bool>(p: &mut Processor) -> Result<(), Fault> {
+    p.pop_w::<D>(R)?;
+    Ok(())
+}
+fn swp<const R: bool, const D: bool>(p: &mut Processor) -> Result<(), Fault> {
+    let c = p.pop_w::<D>(R)?;
+    let b = p.pop_w::<D>(R)?;
+    p.push_w::<D>(c, R)?;
+    p.push_w::<D>(b, R)
+}
+fn rot<const R: bool, const D: bool>(p: &mut Processor) -> Result<(), Fault> {
+    let c = p.pop_w::<D>(R)?;
+    let b = p.pop_w::<D>(R)?;
+    let a = p.pop_w::<D>(R)?;
+    p.push_w::<D>(b, R)?;
+    p.push_w::<D>(a, R)?;
+    p.push_w::<D>(c, R)
+}
+fn dup<const R: bool, const D: bool>(p: &mut Processor) -> Result<(), Fault> {
+    let c = p.pop_w::<D>(R)?;
+    p.push_w::<D>(c, R)?;
+    p.push_w::<D>(c, R)
+}
+fn ovr<const R: bool, const D: bool>(p: &mut Processor) -> Result<(), Fault> {
+    let c = p.pop_w::<D>(R)?;
+    let b = p.pop_w::<D>(R)?;
+    p.push_w::<D>(b, R)?;
+    p.push_w::<D>(c, R)?;
+    p.push_w::<D>(b, R)
+}
+/// STHk copies instead of moving
+fn sth<const K: bool, const R: bool, const D: bool>(p: &mut Processor) -> Result<(), Fault> {
+    let v = p.input_w::<K, D>(R, &mut 0)?;
+    p.push_w::<D>(v, !R)
+}
+
+/// EQU and GTH
+fn compare<const K: bool, const R: bool, const D: bool, const OP: u8>(p: &mut Processor) -> Result<(), Fault> {
+    let mut ofs = 0;
+    let a = p.input_w::<K, D>(R, &mut ofs)?;
+    let b = p.input_w::<K, D>(R, &mut ofs)?;
+    let result = if OP == 0x08 {
+        a == b
+    }
+    else if D {
+        b as i16 > a as i16
+    }
+    else {
+        b as u8 as i8 > a as u8 as i8
+    };
+    p.push(result as u8 * 0xff, R)
+}
+
+/// JMP, JNZ and JSR. the short forms are relative
+fn jump<const K: bool, const R: bool, const D: bool, const OP: u8>(p: &mut Processor) -> Result<(), Fault> {
+    let mut ofs = 0;
+    let addr = p.input_w::<K, D>(R, &mut ofs)?;
+    let will_jump = OP != 0x0b || p.input::<K>(R, &mut ofs)? != 0;
+    if OP == 0x0c {
+        p.push_16(p.pc.wrapping_add(1), !R)?
+    }
+    if will_jump {
+        let dest = if D { addr } else { p.get_pc_offset(addr as u8) };
+        p.pc = dest.wrapping_sub(1)
+    }
+    Ok(())
+}
+
+/// LDZ, LDR and LDA
+fn load<const K: bool, const R: bool, const D: bool, const MODE: u8>(p: &mut Processor) -> Result<(), Fault> {
+    let addr = p.address::<K, R, MODE>(&mut 0)?;
+    let v = if D { p.mem.get_16(addr) } else { p.mem.get(addr) as u16 };
+    p.push_w::<D>(v, R)
+}
+/// STZ, STR and STA
+fn store<const K: bool, const R: bool, const D: bool, const MODE: u8>(p: &mut Processor) -> Result<(), Fault> {
+    let mut ofs = 0;
+    let addr = p.address::<K, R, MODE>(&mut ofs)?;
+    let v = p.input_w::<K, D>(R, &mut ofs)?;
+    if D { p.mem.set_16(addr, v) } else { p.mem.set(addr, v as u8) }
+}
+
+// offset 0 is the top of the stack once the operands are gone. in keep mode they stay, so skip
+// over them to address the same place
+
+fn pic<const K: bool, const R: bool, const D: bool>(p: &mut Processor) -> Result<(), Fault> {
+    let mut ofs = 0;
+    let n = p.input::<K>(R, &mut ofs)?.wrapping_add(ofs);
+    let v = if D { p.pick_16(n, R)? } else { p.pick(n, R)? as u16 };
+    p.push_w::<D>(v, R)
+}
+fn put<const K: bool, const R: bool, const D: bool>(p: &mut Processor) -> Result<(), Fault> {
+    let mut ofs = 0;
+    let n = p.input::<K>(R, &mut ofs)?;
+    let v = p.input_w::<K, D>(R, &mut ofs)?;
+    let n = n.wrapping_add(ofs);
+    if D { p.put_16(v, n, R) } else { p.put(v as u8, n, R) }
+}
+
+/// ADC, SBC, MUL, DVM, AND, IOR and XOR
+fn arith<const K: bool, const R: bool, const D: bool, const OP: u8>(p: &mut Processor) -> Result<(), Fault> {
+    let mut ofs = 0;
+    let a = p.input_w::<K, D>(R, &mut ofs)? as u32;
+    let b = p.input_w::<K, D>(R, &mut ofs)? as u32;
+    let mask = if D { 0xffff } else { 0xff };
+    let carry = (p.st & ST_CARRY) as u32;
+    let x = match OP {
+        0x18 => { // ADC
+            let x = a + b + carry;
+            p.set_carry(x > mask);
+            x
+        }
+        0x19 => { // SBC. carry clear is a borrow
+            let (x, borrow) = b.overflowing_sub(a + (1 - carry));
+            p.set_carry(!borrow);
+            x
+        }
+        0x1a => a * b, // MUL
+        0x1b => { // DVM
+            if a == 0 {
+                return Err(Fault::DivideByZero)
+            }
+            p.push_w::<D>((b / a) as u16, R)?;
+            b % a
+        }
+        0x1c => a & b, // AND
+        0x1d => a | b, // IOR
+        _ => a ^ b // XOR
+    };
+    p.push_w::<D>((x & mask) as u16, R)
+}
+
+/// shifting by the whole width or more leaves nothing
+fn sft<const K: bool, const R: bool, const D: bool>(p: &mut Processor) -> Result<(), Fault> {
+    let mut ofs = 0;
+    let amount = p.input::<K>(R, &mut ofs)?;
+    let v = p.input_w::<K, D>(R, &mut ofs)? as u32;
+    let mask = if D { 0xffff } else { 0xff };
+    let v = ((v << (amount >> 4)) & mask) >> (amount & 0xf);
+    p.push_w::<D>(v as u16, R)
+}
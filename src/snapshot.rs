@@ -0,0 +1,224 @@
+//! whole-machine snapshots
+//! 
+//! the format (all multi-byte values big-endian):
+//! 
+//! off  len     use
+//! 0    4       magic, `41 56 53 00`
+//! 4    1       format version, currently 1
+//! 5    1       wsp
+//! 6    1       rsp
+//! 7    1       st
+//! 8    2       pc
+//! a    ff00    memory, 0x0000 to 0xfeff
+//! ff0a ...     16 device records, one per slot
+//! 
+//! a device record is the devid (0 for an empty slot), a 4 byte length, then that many bytes
+//! of device state. what the state means is up to the device
+
+use crate::processor::Processor;
+use crate::utils::Avc2Error;
+
+const MAGIC: [u8; 4] = [0x41, 0x56, 0x53, 0x00];
+const VERSION: u8 = 1;
+
+impl Processor {
+    pub fn save_snapshot(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend([self.wsp(), self.rsp(), self.st()]);
+        out.extend(self.pc().to_be_bytes());
+        out.extend(self.mem().main());
+        for dev in self.mem().device_states() {
+            match dev {
+                Some((id, state)) => {
+                    out.push(id);
+                    out.extend((state.len() as u32).to_be_bytes());
+                    out.extend(state)
+                }
+                None => out.extend([0; 5])
+            }
+        }
+        out
+    }
+    /// restore a snapshot. the processor must have the same kinds of device in the same slots
+    /// as the one the snapshot was taken from
+    pub fn load_snapshot(&mut self, data: &[u8]) -> Result<(), Avc2Error> {
+        let mut r = StateReader::new(data);
+        if r.bytes(4)? != MAGIC {
+            return Err(Avc2Error::BadSnapshot(String::from("bad signature")))
+        }
+        let version = r.u8()?;
+        if version != VERSION {
+            return Err(Avc2Error::BadSnapshot(format!("unsupported version {}", version)))
+        }
+        let (wsp, rsp, st) = (r.u8()?, r.u8()?, r.u8()?);
+        let pc = r.u16()?;
+        let main = r.bytes(self.mem().main().len())?;
+        let mut states = Vec::new();
+        for _ in 0..16 {
+            let id = r.u8()?;
+            let len = r.u32()? as usize;
+            let state = r.bytes(len)?;
+            states.push(if id == 0 { None } else { Some((id, state)) })
+        }
+        r.finish()?;
+
+        self.mem_mut().load_device_states(&states)?;
+        self.mem_mut().main_mut().copy_from_slice(main);
+        self.set_registers(wsp, rsp, st, pc);
+        Ok(())
+    }
+}
+
+/// reads a snapshot or a device state, failing cleanly if it runs out
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data, pos: 0 }
+    }
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], Avc2Error> {
+        let b = self.data.get(self.pos..self.pos + len).ok_or_else(|| Avc2Error::BadSnapshot(String::from("unexpected end of data")))?;
+        self.pos += len;
+        Ok(b)
+    }
+    pub fn u8(&mut self) -> Result<u8, Avc2Error> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub fn u16(&mut self) -> Result<u16, Avc2Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+    pub fn u64(&mut self) -> Result<u64, Avc2Error> {
+        let b = self.bytes(8)?;
+        Ok(u64::from_be_bytes(b.try_into().unwrap()))
+    }
+    pub fn u32(&mut self) -> Result<u32, Avc2Error> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    /// fail if there's anything left over
+    pub fn finish(&self) -> Result<(), Avc2Error> {
+        if self.pos != self.data.len() {
+            return Err(Avc2Error::BadSnapshot(String::from("trailing data")))
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::Processor;
+    use crate::dev::{DevSpec, Device, WriteResponse};
+    use crate::utils::Avc2Error;
+    use super::StateReader;
+
+    /// a device whose whole state is the last byte written to it
+    struct Latch {
+        val: u8,
+        devid: u8
+    }
+    impl Device for Latch {
+        fn write(&mut self, _addr: u8, val: u8) -> WriteResponse {
+            self.val = val;
+            WriteResponse::None
+        }
+        fn read(&mut self, _addr: u8) -> u8 {
+            self.val
+        }
+        fn devid(&self) -> u8 {
+            self.devid
+        }
+        fn save_state(&self) -> Vec<u8> {
+            vec![self.val]
+        }
+        fn check_state(&self, state: &[u8]) -> Result<(), Avc2Error> {
+            StateReader::new(state).bytes(1).map(|_| ())
+        }
+        fn load_state(&mut self, state: &[u8]) -> Result<(), Avc2Error> {
+            self.val = state[0];
+            Ok(())
+        }
+    }
+    /// a device that doesn't say what it is
+    struct Anonymous;
+    impl Device for Anonymous {
+        fn write(&mut self, _addr: u8, _val: u8) -> WriteResponse {
+            WriteResponse::None
+        }
+        fn read(&mut self, _addr: u8) -> u8 {
+            0
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        // count up in the zero page forever
+        let rom = [0x80, 0x00, 0x10, 0x80, 0x01, 0x18, 0x80, 0x00, 0x11, 0x80, 0xf5, 0x0a];
+        let mut a = Processor::new(&rom, vec![DevSpec::new(3, 3, "")]).unwrap();
+        for _ in 0..100 {
+            a.execute_once();
+        }
+        let snapshot = a.save_snapshot();
+        let mut b = Processor::new(&[], vec![DevSpec::new(3, 3, "")]).unwrap();
+        b.load_snapshot(&snapshot).unwrap();
+        assert_eq!(b.save_snapshot(), snapshot);
+        for _ in 0..100 {
+            a.execute_once();
+            b.execute_once();
+        }
+        assert_eq!(a.save_snapshot(), b.save_snapshot());
+        assert_eq!(b.mem().peek(0), 25);
+
+        let mut c = Processor::new(&[], Vec::new()).unwrap();
+        assert!(c.load_snapshot(&snapshot).is_err()); // no timer
+        assert!(c.load_snapshot(&snapshot[..100]).is_err());
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let mut a = Processor::new(&[], Vec::new()).unwrap();
+        a.attach_device(2, Box::new(Latch { val: 1, devid: 0xf0 })).unwrap();
+        a.attach_device(4, Box::new(Anonymous)).unwrap();
+        let snapshot = a.save_snapshot();
+
+        // devices without a devid are skipped, like empty slots
+        let mut b = Processor::new(&[], Vec::new()).unwrap();
+        b.attach_device(2, Box::new(Latch { val: 2, devid: 0xf0 })).unwrap();
+        b.load_snapshot(&snapshot).unwrap();
+        assert_eq!(b.mem().device_states()[2], Some((0xf0, vec![1])));
+
+        // slot 3 doesn't match, so slot 2 isn't loaded either
+        let mut c = Processor::new(&[], Vec::new()).unwrap();
+        c.attach_device(2, Box::new(Latch { val: 2, devid: 0xf0 })).unwrap();
+        c.attach_device(3, Box::new(Latch { val: 3, devid: 0xf1 })).unwrap();
+        assert!(c.load_snapshot(&snapshot).is_err());
+        assert_eq!(c.mem().device_states()[2], Some((0xf0, vec![2])));
+    }
+
+    #[test]
+    fn test_truncated() {
+        // the system device in slot 0 has moved on, but the timer's state is cut short
+        let rom = [0x80, 0x05, 0x80, 0xfe, 0x0a]; // spin
+        let mut a = Processor::new(&rom, vec![DevSpec::new(3, 3, "")]).unwrap();
+        for _ in 0..100 {
+            a.execute_once();
+        }
+        let states = a.mem().device_states();
+        let mut b = Processor::new(&rom, vec![DevSpec::new(3, 3, "")]).unwrap();
+        let before = b.mem().device_states();
+        assert_ne!(states[0], before[0]);
+
+        let truncated: Vec<_> = states.iter().enumerate().map(|(i, s)| s.as_ref().map(|(id, state)| {
+            (*id, if i == 3 { &state[..4] } else { &state[..] })
+        })).collect();
+        assert!(b.mem_mut().load_device_states(&truncated).is_err());
+        assert_eq!(b.mem().device_states(), before);
+
+        let whole: Vec<_> = states.iter().map(|s| s.as_ref().map(|(id, state)| (*id, &state[..]))).collect();
+        b.mem_mut().load_device_states(&whole).unwrap();
+        assert_eq!(b.mem().device_states(), states);
+    }
+}
@@ -0,0 +1,122 @@
+//! symbol files, mapping addresses to labels and source lines
+//!
+//! the assembler writes one next to each rom, with the extension `.sym`. each line is either
+//! `label ADDR NAME` or `line ADDR LEN LINE FILE`, saying the LEN bytes from ADDR came from
+//! line LINE of FILE. addresses and lengths are hex, line numbers decimal
+
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::utils::Avc2Error;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    labels: BTreeMap<u16, Vec<String>>,
+    /// start address -> (length, line, file)
+    lines: BTreeMap<u16, (u16, usize, String)>
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn parse(s: &str) -> Result<Symbols, Avc2Error> {
+        let mut symbols = Symbols::new();
+        for (i, line) in s.lines().enumerate() {
+            let bad_line = || Avc2Error::BadSymbols(format!("line {}: {}", i + 1, line));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let mut words = line.splitn(5, ' ');
+            let kind = words.next();
+            let addr = words.next().and_then(|a| u16::from_str_radix(a, 16).ok()).ok_or_else(bad_line)?;
+            match kind {
+                Some("label") => {
+                    let name = words.next().filter(|n| !n.is_empty()).ok_or_else(bad_line)?;
+                    symbols.add_label(addr, name)
+                }
+                Some("line") => {
+                    let len = words.next().and_then(|l| u16::from_str_radix(l, 16).ok()).ok_or_else(bad_line)?;
+                    let line = words.next().and_then(|l| l.parse().ok()).ok_or_else(bad_line)?;
+                    let file = words.next().ok_or_else(bad_line)?;
+                    symbols.add_line(addr, len, line, file)
+                }
+                _ => return Err(bad_line())
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn add_label(&mut self, addr: u16, name: &str) {
+        self.labels.entry(addr).or_default().push(name.to_string())
+    }
+    /// say the `len` bytes from `addr` came from `line` of `file`
+    pub fn add_line(&mut self, addr: u16, len: u16, line: usize, file: &str) {
+        self.lines.insert(addr, (len, line, file.to_string()));
+    }
+
+    /// the address of a label
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.iter().find(|(_, names)| names.iter().any(|n| n == name)).map(|(a, _)| *a)
+    }
+
+    /// the source file and line an address was assembled from
+    pub fn source(&self, addr: u16) -> Option<(&str, usize)> {
+        let (start, (len, line, file)) = self.lines.range(..=addr).next_back()?;
+        if (addr - start) < *len {
+            Some((file, *line))
+        }
+        else {
+            None
+        }
+    }
+
+    /// describe an address like `loop+3 (main.avc:42)`, if it's in the assembled code
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        let source = self.source(addr)?;
+        let label = self.labels.range(..=addr).next_back().map(|(a, names)| match addr - a {
+            0 => names[0].clone(),
+            ofs => format!("{}+{}", names[0], ofs)
+        });
+        Some(match label {
+            Some(label) => format!("{} ({}:{})", label, source.0, source.1),
+            None => format!("{}:{}", source.0, source.1)
+        })
+    }
+}
+
+impl fmt::Display for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# avc2 symbols")?;
+        for (addr, names) in &self.labels {
+            for name in names {
+                writeln!(f, "label {:04x} {}", addr, name)?
+            }
+        }
+        for (addr, (len, line, file)) in &self.lines {
+            writeln!(f, "line {:04x} {:x} {} {}", addr, len, line, file)?
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut s = Symbols::new();
+        s.add_label(0x0300, "start");
+        s.add_label(0x0305, "loop");
+        s.add_line(0x0300, 5, 1, "main.avc");
+        s.add_line(0x0305, 3, 42, "my lib.avc");
+        let s = Symbols::parse(&s.to_string()).unwrap();
+        assert_eq!(s.describe(0x0300).unwrap(), "start (main.avc:1)");
+        assert_eq!(s.describe(0x0307).unwrap(), "loop+2 (my lib.avc:42)");
+        assert_eq!(s.describe(0x0308), None);
+        assert_eq!(s.label("loop"), Some(0x0305));
+        assert!(Symbols::parse("label zz x").is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! execution traces, one record per instruction
+
+use std::io::{self, Write};
+use std::str::FromStr;
+use crate::processor::Processor;
+use crate::opcodes;
+use crate::symbols::Symbols;
+
+/// how many bytes from the top of each stack go in a text trace line
+const STACK_BYTES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    /// a line of text per instruction
+    #[default]
+    Text,
+    /// a fixed size record per instruction, after a header
+    Binary
+}
+impl FromStr for TraceFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(format!("unknown trace format {} (expected text or binary)", s))
+        }
+    }
+}
+
+/// writes a record of each instruction before it executes. call `record` before every
+/// `Processor::execute_once`
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    started: bool,
+    symbols: Symbols
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, format: TraceFormat) -> Tracer {
+        Tracer {
+            out,
+            format,
+            started: false,
+            symbols: Symbols::new()
+        }
+    }
+    /// label text traces with these symbols
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols
+    }
+
+    /// record the instruction the processor is about to execute
+    pub fn record(&mut self, p: &Processor) -> io::Result<()> {
+        let pc = p.pc();
+        let instr = p.mem().peek(pc);
+        let operand = literal(p, pc, instr);
+        match self.format {
+            TraceFormat::Text => {
+                let operand = match operand {
+                    Some((v, true)) => format!("{:04x}", v),
+                    Some((v, false)) => format!("{:02x}", v),
+                    None => String::new()
+                };
+                write!(self.out, "{:04x}  {:02x}  {:<8}{:<6}wst {:>11}  rst {:>11}  c{}",
+                    pc, instr, opcodes::describe(instr), operand,
+                    stack_top(&p.working_stack()), stack_top(&p.return_stack()), p.st() & 1
+                )?;
+                match self.symbols.describe(pc) {
+                    Some(place) => writeln!(self.out, "  {}", place),
+                    None => writeln!(self.out)
+                }
+            }
+            TraceFormat::Binary => {
+                if !self.started {
+                    self.out.write_all(b"AVT\0")?;
+                    self.out.write_all(&[1])?; // version
+                    self.started = true
+                }
+                let [hb, lb] = pc.to_be_bytes();
+                let [ohb, olb] = operand.map_or(0, |(v, _)| v).to_be_bytes();
+                self.out.write_all(&[hb, lb, instr, ohb, olb, p.wsp(), p.rsp(), p.st()])
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// the operand of a LIT or LIT2 at `pc`, and whether it's 16 bits
+fn literal(p: &Processor, pc: u16, instr: u8) -> Option<(u16, bool)> {
+    let hb = p.mem().peek(pc.wrapping_add(1));
+    match opcodes::info(instr).operand {
+        1 => Some((hb as u16, false)),
+        2 => Some((u16::from_be_bytes([hb, p.mem().peek(pc.wrapping_add(2))]), true)),
+        _ => None
+    }
+}
+
+/// the top few bytes of a stack, top last
+fn stack_top(stack: &[u8]) -> String {
+    let start = stack.len().saturating_sub(STACK_BYTES);
+    let top: Vec<String> = stack[start..].iter().map(|b| format!("{:02x}", b)).collect();
+    top.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    /// lets the test read what the tracer wrote
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_text() {
+        let rom = [0xa0, 0x12, 0x34, 0x80, 0x56, 0x20, 0x18];
+        let mut p = Processor::new(&rom, Vec::new()).unwrap();
+        let out = Shared::default();
+        let mut t = Tracer::new(Box::new(out.clone()), TraceFormat::Text);
+        let mut symbols = Symbols::new();
+        symbols.add_label(0x0305, "carry");
+        symbols.add_line(0x0305, 2, 3, "main.avc");
+        t.set_symbols(symbols);
+        for _ in 0..4 {
+            t.record(&p).unwrap();
+            p.execute_once();
+        }
+        let trace = String::from_utf8(out.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines, [
+            "0300  a0  LIT2    1234  wst              rst              c0",
+            "0303  80  LIT     56    wst       34 12  rst              c0",
+            "0305  20  SEC           wst    34 12 56  rst              c0  carry (main.avc:3)",
+            "0306  18  ADC           wst    34 12 56  rst              c1  carry+1 (main.avc:3)",
+        ]);
+    }
+}
@@ -8,7 +8,50 @@ pub enum Avc2Error {
     #[error("device init error: {0}")]
     DevInitError(String),
     #[error("bad device spec: {0}")]
-    BadDevSpec(String)
+    BadDevSpec(String),
+    #[error("{0}")]
+    Fault(#[from] Fault),
+    #[error("bad snapshot: {0}")]
+    BadSnapshot(String),
+    #[error("bad input script: {0}")]
+    BadInputScript(String),
+    #[error("assembly error: {0}")]
+    AsmError(String),
+    #[error("bad symbol file: {0}")]
+    BadSymbols(String)
+}
+
+/// something the guest program did that the cpu can't carry on from
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    #[error("divide by zero")]
+    DivideByZero,
+    #[error("debug break")]
+    DebugBreak,
+    #[error("undefined instruction")]
+    UndefinedInstruction,
+    #[error("rom is too large ({0} bytes, the limit is 0xfc00)")]
+    RomTooLarge(usize),
+    #[error("bad device access: {0}")]
+    BadDeviceAccess(String),
+    #[error("{0} stack overflow")]
+    StackOverflow(Stack),
+    #[error("{0} stack underflow")]
+    StackUnderflow(Stack)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stack {
+    Working,
+    Return
+}
+impl std::fmt::Display for Stack {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Stack::Working => write!(f, "working"),
+            Stack::Return => write!(f, "return")
+        }
+    }
 }
 
 pub fn set_hb(main: u16, hb: u8) -> u16 {
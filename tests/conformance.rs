@@ -0,0 +1,468 @@
+//! one test for each opcode and mode combination, checked against specification.md
+//!
+//! each case pushes its starting stacks with LIT and LITr (bottom first, so a 16-bit value
+//! 0x1234 is written `0x34, 0x12`), then runs its code to the end. the code starts at 0x0300
+//! plus two bytes for each byte on the stacks, and one more if the carry is set. every case runs
+//! twice, once one instruction at a time and once with the block cache
+
+use avc2::{Fault, Processor, Status};
+
+struct Case {
+    code: Vec<u8>,
+    wst: Vec<u8>,
+    rst: Vec<u8>,
+    carry: bool,
+    pokes: Vec<(u16, Vec<u8>)>,
+    expect_wst: Option<Vec<u8>>,
+    expect_rst: Option<Vec<u8>>,
+    expect_carry: Option<bool>,
+    expect_mem: Vec<(u16, Vec<u8>)>
+}
+
+impl Case {
+    fn new(code: &[u8]) -> Case {
+        Case {
+            code: code.to_vec(),
+            wst: Vec::new(),
+            rst: Vec::new(),
+            carry: false,
+            pokes: Vec::new(),
+            expect_wst: None,
+            expect_rst: None,
+            expect_carry: None,
+            expect_mem: Vec::new()
+        }
+    }
+    fn wst(mut self, wst: &[u8]) -> Case {
+        self.wst = wst.to_vec();
+        self
+    }
+    fn rst(mut self, rst: &[u8]) -> Case {
+        self.rst = rst.to_vec();
+        self
+    }
+    /// set the carry flag before the code runs
+    fn carry(mut self) -> Case {
+        self.carry = true;
+        self
+    }
+    /// write to memory before the code runs
+    fn poke(mut self, addr: u16, bytes: &[u8]) -> Case {
+        self.pokes.push((addr, bytes.to_vec()));
+        self
+    }
+    /// the working stack afterwards. without this, it's expected to be unchanged
+    fn expect_wst(mut self, wst: &[u8]) -> Case {
+        self.expect_wst = Some(wst.to_vec());
+        self
+    }
+    /// the return stack afterwards. without this, it's expected to be unchanged
+    fn expect_rst(mut self, rst: &[u8]) -> Case {
+        self.expect_rst = Some(rst.to_vec());
+        self
+    }
+    fn expect_carry(mut self, carry: bool) -> Case {
+        self.expect_carry = Some(carry);
+        self
+    }
+    fn expect_mem(mut self, addr: u16, bytes: &[u8]) -> Case {
+        self.expect_mem.push((addr, bytes.to_vec()));
+        self
+    }
+
+    fn check(self) {
+        let mut rom = Vec::new();
+        for b in &self.wst {
+            rom.extend([0x80, *b])
+        }
+        for b in &self.rst {
+            rom.extend([0xc0, *b])
+        }
+        if self.carry {
+            rom.push(0x20)
+        }
+        rom.extend(&self.code);
+        let end = 0x0300 + rom.len() as u16;
+        // blocks can't be decoded past a debug break, so they stop at the end too
+        rom.push(0xef);
+
+        for blocks in [false, true] {
+            self.run(&rom, end, blocks)
+        }
+    }
+
+    fn run(&self, rom: &[u8], end: u16, blocks: bool) {
+        let engine = if blocks { "blocks" } else { "interpreter" };
+        let mut p = Processor::new(rom, Vec::new()).unwrap();
+        p.set_block_cache(blocks);
+        for (addr, bytes) in &self.pokes {
+            let addr = *addr as usize;
+            p.mem_mut().main_mut()[addr..addr + bytes.len()].copy_from_slice(bytes)
+        }
+        let mut steps = 0;
+        while p.pc() != end {
+            let (status, ran) = p.execute_block();
+            assert_eq!(status, Status::Running, "{}: at {:04x}", engine, p.pc());
+            steps += ran;
+            assert!(steps < 64, "{}: didn't reach the end of the code", engine);
+        }
+
+        let wst = self.expect_wst.as_ref().unwrap_or(&self.wst);
+        let rst = self.expect_rst.as_ref().unwrap_or(&self.rst);
+        assert_eq!(&p.working_stack(), wst, "{}: working stack", engine);
+        assert_eq!(&p.return_stack(), rst, "{}: return stack", engine);
+        if let Some(carry) = self.expect_carry {
+            assert_eq!(p.st() & 1 != 0, carry, "{}: carry", engine)
+        }
+        for (addr, bytes) in &self.expect_mem {
+            let addr = *addr as usize;
+            assert_eq!(&p.mem().main()[addr..addr + bytes.len()], &bytes[..], "{}: memory at {:04x}", engine, addr)
+        }
+    }
+}
+
+macro_rules! cases {
+    ($($name:ident: $case:expr;)*) => {$(
+        #[test]
+        fn $name() {
+            $case.check()
+        }
+    )*}
+}
+
+// 3.1: stack primitives. keep mode is undefined for these, apart from STH
+cases! {
+    pop: Case::new(&[0x03]).wst(&[1, 2, 3]).expect_wst(&[1, 2]);
+    popr: Case::new(&[0x43]).rst(&[1, 2, 3]).expect_rst(&[1, 2]);
+    pop2: Case::new(&[0x23]).wst(&[1, 2, 3]).expect_wst(&[1]);
+    popr2: Case::new(&[0x63]).rst(&[1, 2, 3]).expect_rst(&[1]);
+    swp: Case::new(&[0x04]).wst(&[1, 2, 3]).expect_wst(&[1, 3, 2]);
+    swpr: Case::new(&[0x44]).rst(&[1, 2, 3]).expect_rst(&[1, 3, 2]);
+    swp2: Case::new(&[0x24]).wst(&[1, 2, 3, 4, 5]).expect_wst(&[1, 4, 5, 2, 3]);
+    swpr2: Case::new(&[0x64]).rst(&[1, 2, 3, 4, 5]).expect_rst(&[1, 4, 5, 2, 3]);
+    rot: Case::new(&[0x05]).wst(&[1, 2, 3]).expect_wst(&[2, 1, 3]);
+    rotr: Case::new(&[0x45]).rst(&[1, 2, 3]).expect_rst(&[2, 1, 3]);
+    rot2: Case::new(&[0x25]).wst(&[1, 2, 3, 4, 5, 6]).expect_wst(&[3, 4, 1, 2, 5, 6]);
+    rotr2: Case::new(&[0x65]).rst(&[1, 2, 3, 4, 5, 6]).expect_rst(&[3, 4, 1, 2, 5, 6]);
+    dup: Case::new(&[0x06]).wst(&[1, 2]).expect_wst(&[1, 2, 2]);
+    dupr: Case::new(&[0x46]).rst(&[1, 2]).expect_rst(&[1, 2, 2]);
+    dup2: Case::new(&[0x26]).wst(&[1, 2]).expect_wst(&[1, 2, 1, 2]);
+    dupr2: Case::new(&[0x66]).rst(&[1, 2]).expect_rst(&[1, 2, 1, 2]);
+    ovr: Case::new(&[0x07]).wst(&[1, 2, 3]).expect_wst(&[1, 2, 3, 2]);
+    ovrr: Case::new(&[0x47]).rst(&[1, 2, 3]).expect_rst(&[1, 2, 3, 2]);
+    ovr2: Case::new(&[0x27]).wst(&[1, 2, 3, 4]).expect_wst(&[1, 2, 3, 4, 1, 2]);
+    ovrr2: Case::new(&[0x67]).rst(&[1, 2, 3, 4]).expect_rst(&[1, 2, 3, 4, 1, 2]);
+}
+
+// 3.2: logic and jumps
+cases! {
+    sth: Case::new(&[0x0d]).wst(&[1, 2]).expect_wst(&[1]).expect_rst(&[2]);
+    sthk: Case::new(&[0x8d]).wst(&[1, 2]).expect_rst(&[2]);
+    sthr: Case::new(&[0x4d]).rst(&[1, 2]).expect_rst(&[1]).expect_wst(&[2]);
+    sthkr: Case::new(&[0xcd]).rst(&[1, 2]).expect_wst(&[2]);
+    sth2: Case::new(&[0x2d]).wst(&[1, 2, 3]).expect_wst(&[1]).expect_rst(&[2, 3]);
+    sthk2: Case::new(&[0xad]).wst(&[1, 2, 3]).expect_rst(&[2, 3]);
+    sthr2: Case::new(&[0x6d]).rst(&[1, 2, 3]).expect_rst(&[1]).expect_wst(&[2, 3]);
+    sthkr2: Case::new(&[0xed]).rst(&[1, 2, 3]).expect_wst(&[2, 3]);
+
+    equ: Case::new(&[0x08]).wst(&[1, 1]).expect_wst(&[0xff]);
+    equ_false: Case::new(&[0x08]).wst(&[1, 2]).expect_wst(&[0x00]);
+    equk: Case::new(&[0x88]).wst(&[1, 1]).expect_wst(&[1, 1, 0xff]);
+    equr: Case::new(&[0x48]).rst(&[1, 2]).expect_rst(&[0x00]);
+    equkr: Case::new(&[0xc8]).rst(&[1, 1]).expect_rst(&[1, 1, 0xff]);
+    equ2: Case::new(&[0x28]).wst(&[0x34, 0x12, 0x34, 0x12]).expect_wst(&[0xff]);
+    equ2_false: Case::new(&[0x28]).wst(&[0x34, 0x12, 0x34, 0x13]).expect_wst(&[0x00]);
+    equk2: Case::new(&[0xa8]).wst(&[0x34, 0x12, 0x34, 0x12]).expect_wst(&[0x34, 0x12, 0x34, 0x12, 0xff]);
+    equr2: Case::new(&[0x68]).rst(&[0x34, 0x12, 0x34, 0x12]).expect_rst(&[0xff]);
+    equkr2: Case::new(&[0xe8]).rst(&[0x34, 0x12, 0x12, 0x34]).expect_rst(&[0x34, 0x12, 0x12, 0x34, 0x00]);
+
+    // GTH runs `a > b`, where b is on top
+    gth: Case::new(&[0x09]).wst(&[2, 1]).expect_wst(&[0xff]);
+    gth_false: Case::new(&[0x09]).wst(&[1, 2]).expect_wst(&[0x00]);
+    gth_equal: Case::new(&[0x09]).wst(&[2, 2]).expect_wst(&[0x00]);
+    gth_signed: Case::new(&[0x09]).wst(&[0x01, 0xff]).expect_wst(&[0xff]);
+    gth_signed_false: Case::new(&[0x09]).wst(&[0x80, 0x7f]).expect_wst(&[0x00]);
+    gthk: Case::new(&[0x89]).wst(&[2, 1]).expect_wst(&[2, 1, 0xff]);
+    gthr: Case::new(&[0x49]).rst(&[2, 1]).expect_rst(&[0xff]);
+    gthkr: Case::new(&[0xc9]).rst(&[1, 2]).expect_rst(&[1, 2, 0x00]);
+    gth2: Case::new(&[0x29]).wst(&[0x00, 0x01, 0xff, 0x00]).expect_wst(&[0xff]);
+    gth2_false: Case::new(&[0x29]).wst(&[0xff, 0x00, 0x00, 0x01]).expect_wst(&[0x00]);
+    gth2_signed: Case::new(&[0x29]).wst(&[0x01, 0x00, 0xff, 0xff]).expect_wst(&[0xff]);
+    gth2_signed_false: Case::new(&[0x29]).wst(&[0x00, 0x80, 0xff, 0x7f]).expect_wst(&[0x00]);
+    gthk2: Case::new(&[0xa9]).wst(&[0x00, 0x01, 0xff, 0x00]).expect_wst(&[0x00, 0x01, 0xff, 0x00, 0xff]);
+    gthr2: Case::new(&[0x69]).rst(&[0x01, 0x00, 0xff, 0xff]).expect_rst(&[0xff]);
+    gthkr2: Case::new(&[0xe9]).rst(&[0xff, 0xff, 0x01, 0x00]).expect_rst(&[0xff, 0xff, 0x01, 0x00, 0x00]);
+
+    // the jumps skip `LIT aa` to land on `LIT bb`, 3 bytes on from the jump
+    jmp: Case::new(&[0x0a, 0x80, 0xaa, 0x80, 0xbb]).wst(&[3]).expect_wst(&[0xbb]);
+    jmpk: Case::new(&[0x8a, 0x80, 0xaa, 0x80, 0xbb]).wst(&[3]).expect_wst(&[3, 0xbb]);
+    jmpr: Case::new(&[0x4a, 0x80, 0xaa, 0x80, 0xbb]).rst(&[3]).expect_rst(&[]).expect_wst(&[0xbb]);
+    jmpkr: Case::new(&[0xca, 0x80, 0xaa, 0x80, 0xbb]).rst(&[3]).expect_wst(&[0xbb]);
+    jmp2: Case::new(&[0x2a, 0x80, 0xaa, 0x80, 0xbb]).wst(&[0x07, 0x03]).expect_wst(&[0xbb]);
+    jmpk2: Case::new(&[0xaa, 0x80, 0xaa, 0x80, 0xbb]).wst(&[0x07, 0x03]).expect_wst(&[0x07, 0x03, 0xbb]);
+    jmpr2: Case::new(&[0x6a, 0x80, 0xaa, 0x80, 0xbb]).rst(&[0x07, 0x03]).expect_rst(&[]).expect_wst(&[0xbb]);
+    jmpkr2: Case::new(&[0xea, 0x80, 0xaa, 0x80, 0xbb]).rst(&[0x07, 0x03]).expect_wst(&[0xbb]);
+
+    jnz: Case::new(&[0x0b, 0x80, 0xaa, 0x80, 0xbb]).wst(&[1, 3]).expect_wst(&[0xbb]);
+    jnz_not_taken: Case::new(&[0x0b, 0x80, 0xaa, 0x80, 0xbb]).wst(&[0, 3]).expect_wst(&[0xaa, 0xbb]);
+    jnzk: Case::new(&[0x8b, 0x80, 0xaa, 0x80, 0xbb]).wst(&[1, 3]).expect_wst(&[1, 3, 0xbb]);
+    jnzr: Case::new(&[0x4b, 0x80, 0xaa, 0x80, 0xbb]).rst(&[1, 3]).expect_rst(&[]).expect_wst(&[0xbb]);
+    jnzkr: Case::new(&[0xcb, 0x80, 0xaa, 0x80, 0xbb]).rst(&[0, 3]).expect_wst(&[0xaa, 0xbb]);
+    jnz2: Case::new(&[0x2b, 0x80, 0xaa, 0x80, 0xbb]).wst(&[1, 0x09, 0x03]).expect_wst(&[0xbb]);
+    jnz2_not_taken: Case::new(&[0x2b, 0x80, 0xaa, 0x80, 0xbb]).wst(&[0, 0x09, 0x03]).expect_wst(&[0xaa, 0xbb]);
+    jnzk2: Case::new(&[0xab, 0x80, 0xaa, 0x80, 0xbb]).wst(&[1, 0x09, 0x03]).expect_wst(&[1, 0x09, 0x03, 0xbb]);
+    jnzr2: Case::new(&[0x6b, 0x80, 0xaa, 0x80, 0xbb]).rst(&[1, 0x09, 0x03]).expect_rst(&[]).expect_wst(&[0xbb]);
+    jnzkr2: Case::new(&[0xeb, 0x80, 0xaa, 0x80, 0xbb]).rst(&[0, 0x09, 0x03]).expect_wst(&[0xaa, 0xbb]);
+
+    // JSR pushes the address after itself. the leading no-op keeps its two bytes apart
+    jsr: Case::new(&[0x00, 0x0c, 0x80, 0xaa, 0x80, 0xbb]).wst(&[3]).expect_wst(&[0xbb]).expect_rst(&[0x04, 0x03]);
+    jsrk: Case::new(&[0x00, 0x8c, 0x80, 0xaa, 0x80, 0xbb]).wst(&[3]).expect_wst(&[3, 0xbb]).expect_rst(&[0x04, 0x03]);
+    jsrr: Case::new(&[0x00, 0x4c, 0x80, 0xaa, 0x80, 0xbb]).rst(&[3]).expect_rst(&[]).expect_wst(&[0x04, 0x03, 0xbb]);
+    jsrkr: Case::new(&[0x00, 0xcc, 0x80, 0xaa, 0x80, 0xbb]).rst(&[3]).expect_wst(&[0x04, 0x03, 0xbb]);
+    jsr2: Case::new(&[0x00, 0x2c, 0x80, 0xaa, 0x80, 0xbb]).wst(&[0x08, 0x03]).expect_wst(&[0xbb]).expect_rst(&[0x06, 0x03]);
+    jsrk2: Case::new(&[0x00, 0xac, 0x80, 0xaa, 0x80, 0xbb]).wst(&[0x08, 0x03]).expect_wst(&[0x08, 0x03, 0xbb]).expect_rst(&[0x06, 0x03]);
+    jsrr2: Case::new(&[0x00, 0x6c, 0x80, 0xaa, 0x80, 0xbb]).rst(&[0x08, 0x03]).expect_rst(&[]).expect_wst(&[0x06, 0x03, 0xbb]);
+    jsrkr2: Case::new(&[0x00, 0xec, 0x80, 0xaa, 0x80, 0xbb]).rst(&[0x08, 0x03]).expect_wst(&[0x06, 0x03, 0xbb]);
+}
+
+// 3.3: memory accesses
+cases! {
+    ldz: Case::new(&[0x10]).wst(&[0x10]).poke(0x0010, &[0xaa]).expect_wst(&[0xaa]);
+    ldzk: Case::new(&[0x90]).wst(&[0x10]).poke(0x0010, &[0xaa]).expect_wst(&[0x10, 0xaa]);
+    ldzr: Case::new(&[0x50]).rst(&[0x10]).poke(0x0010, &[0xaa]).expect_rst(&[0xaa]);
+    ldzkr: Case::new(&[0xd0]).rst(&[0x10]).poke(0x0010, &[0xaa]).expect_rst(&[0x10, 0xaa]);
+    ldz2: Case::new(&[0x30]).wst(&[0x10]).poke(0x0010, &[0x12, 0x34]).expect_wst(&[0x34, 0x12]);
+    ldzk2: Case::new(&[0xb0]).wst(&[0x10]).poke(0x0010, &[0x12, 0x34]).expect_wst(&[0x10, 0x34, 0x12]);
+    ldzr2: Case::new(&[0x70]).rst(&[0x10]).poke(0x0010, &[0x12, 0x34]).expect_rst(&[0x34, 0x12]);
+    ldzkr2: Case::new(&[0xf0]).rst(&[0x10]).poke(0x0010, &[0x12, 0x34]).expect_rst(&[0x10, 0x34, 0x12]);
+
+    stz: Case::new(&[0x11]).wst(&[0xaa, 0x10]).expect_wst(&[]).expect_mem(0x0010, &[0xaa]);
+    stzk: Case::new(&[0x91]).wst(&[0xaa, 0x10]).expect_mem(0x0010, &[0xaa]);
+    stzr: Case::new(&[0x51]).rst(&[0xaa, 0x10]).expect_rst(&[]).expect_mem(0x0010, &[0xaa]);
+    stzkr: Case::new(&[0xd1]).rst(&[0xaa, 0x10]).expect_mem(0x0010, &[0xaa]);
+    stz2: Case::new(&[0x31]).wst(&[0x34, 0x12, 0x10]).expect_wst(&[]).expect_mem(0x0010, &[0x12, 0x34]);
+    stzk2: Case::new(&[0xb1]).wst(&[0x34, 0x12, 0x10]).expect_mem(0x0010, &[0x12, 0x34]);
+    stzr2: Case::new(&[0x71]).rst(&[0x34, 0x12, 0x10]).expect_rst(&[]).expect_mem(0x0010, &[0x12, 0x34]);
+    stzkr2: Case::new(&[0xf1]).rst(&[0x34, 0x12, 0x10]).expect_mem(0x0010, &[0x12, 0x34]);
+
+    // the loads read the `LIT aa` after them, which then runs
+    ldr: Case::new(&[0x12, 0x80, 0xaa]).wst(&[2]).expect_wst(&[0xaa, 0xaa]);
+    ldr_backwards: Case::new(&[0x12]).wst(&[0xfe]).expect_wst(&[0x80]);
+    ldrk: Case::new(&[0x92, 0x80, 0xaa]).wst(&[2]).expect_wst(&[2, 0xaa, 0xaa]);
+    ldrr: Case::new(&[0x52, 0x80, 0xaa]).rst(&[2]).expect_rst(&[0xaa]).expect_wst(&[0xaa]);
+    ldrkr: Case::new(&[0xd2, 0x80, 0xaa]).rst(&[2]).expect_rst(&[2, 0xaa]).expect_wst(&[0xaa]);
+    ldr2: Case::new(&[0x32, 0x80, 0xaa]).wst(&[1]).expect_wst(&[0xaa, 0x80, 0xaa]);
+    ldrk2: Case::new(&[0xb2, 0x80, 0xaa]).wst(&[1]).expect_wst(&[1, 0xaa, 0x80, 0xaa]);
+    ldrr2: Case::new(&[0x72, 0x80, 0xaa]).rst(&[1]).expect_rst(&[0xaa, 0x80]).expect_wst(&[0xaa]);
+    ldrkr2: Case::new(&[0xf2, 0x80, 0xaa]).rst(&[1]).expect_rst(&[1, 0xaa, 0x80]).expect_wst(&[0xaa]);
+
+    // the stores write just past the end of the code
+    str: Case::new(&[0x13]).wst(&[0xaa, 1]).expect_wst(&[]).expect_mem(0x0305, &[0xaa]);
+    strk: Case::new(&[0x93]).wst(&[0xaa, 1]).expect_mem(0x0305, &[0xaa]);
+    strr: Case::new(&[0x53]).rst(&[0xaa, 1]).expect_rst(&[]).expect_mem(0x0305, &[0xaa]);
+    strkr: Case::new(&[0xd3]).rst(&[0xaa, 1]).expect_mem(0x0305, &[0xaa]);
+    str2: Case::new(&[0x33]).wst(&[0x34, 0x12, 1]).expect_wst(&[]).expect_mem(0x0307, &[0x12, 0x34]);
+    strk2: Case::new(&[0xb3]).wst(&[0x34, 0x12, 1]).expect_mem(0x0307, &[0x12, 0x34]);
+    strr2: Case::new(&[0x73]).rst(&[0x34, 0x12, 1]).expect_rst(&[]).expect_mem(0x0307, &[0x12, 0x34]);
+    strkr2: Case::new(&[0xf3]).rst(&[0x34, 0x12, 1]).expect_mem(0x0307, &[0x12, 0x34]);
+
+    lda: Case::new(&[0x14]).wst(&[0x00, 0x20]).poke(0x2000, &[0xaa]).expect_wst(&[0xaa]);
+    ldak: Case::new(&[0x94]).wst(&[0x00, 0x20]).poke(0x2000, &[0xaa]).expect_wst(&[0x00, 0x20, 0xaa]);
+    ldar: Case::new(&[0x54]).rst(&[0x00, 0x20]).poke(0x2000, &[0xaa]).expect_rst(&[0xaa]);
+    ldakr: Case::new(&[0xd4]).rst(&[0x00, 0x20]).poke(0x2000, &[0xaa]).expect_rst(&[0x00, 0x20, 0xaa]);
+    lda2: Case::new(&[0x34]).wst(&[0x00, 0x20]).poke(0x2000, &[0x12, 0x34]).expect_wst(&[0x34, 0x12]);
+    ldak2: Case::new(&[0xb4]).wst(&[0x00, 0x20]).poke(0x2000, &[0x12, 0x34]).expect_wst(&[0x00, 0x20, 0x34, 0x12]);
+    ldar2: Case::new(&[0x74]).rst(&[0x00, 0x20]).poke(0x2000, &[0x12, 0x34]).expect_rst(&[0x34, 0x12]);
+    ldakr2: Case::new(&[0xf4]).rst(&[0x00, 0x20]).poke(0x2000, &[0x12, 0x34]).expect_rst(&[0x00, 0x20, 0x34, 0x12]);
+
+    sta: Case::new(&[0x15]).wst(&[0xaa, 0x00, 0x20]).expect_wst(&[]).expect_mem(0x2000, &[0xaa]);
+    stak: Case::new(&[0x95]).wst(&[0xaa, 0x00, 0x20]).expect_mem(0x2000, &[0xaa]);
+    star: Case::new(&[0x55]).rst(&[0xaa, 0x00, 0x20]).expect_rst(&[]).expect_mem(0x2000, &[0xaa]);
+    stakr: Case::new(&[0xd5]).rst(&[0xaa, 0x00, 0x20]).expect_mem(0x2000, &[0xaa]);
+    sta2: Case::new(&[0x35]).wst(&[0x34, 0x12, 0x00, 0x20]).expect_wst(&[]).expect_mem(0x2000, &[0x12, 0x34]);
+    stak2: Case::new(&[0xb5]).wst(&[0x34, 0x12, 0x00, 0x20]).expect_mem(0x2000, &[0x12, 0x34]);
+    star2: Case::new(&[0x75]).rst(&[0x34, 0x12, 0x00, 0x20]).expect_rst(&[]).expect_mem(0x2000, &[0x12, 0x34]);
+    stakr2: Case::new(&[0xf5]).rst(&[0x34, 0x12, 0x00, 0x20]).expect_mem(0x2000, &[0x12, 0x34]);
+
+    // offset 0 is the top of the stack once the operands are gone. keep mode addresses the
+    // same place
+    pic: Case::new(&[0x16]).wst(&[1, 2, 3, 1]).expect_wst(&[1, 2, 3, 2]);
+    pic_top: Case::new(&[0x16]).wst(&[1, 2, 3, 0]).expect_wst(&[1, 2, 3, 3]);
+    pick: Case::new(&[0x96]).wst(&[1, 2, 3, 1]).expect_wst(&[1, 2, 3, 1, 2]);
+    picr: Case::new(&[0x56]).rst(&[1, 2, 3, 2]).expect_rst(&[1, 2, 3, 1]);
+    pickr: Case::new(&[0xd6]).rst(&[1, 2, 3, 2]).expect_rst(&[1, 2, 3, 2, 1]);
+    pic2: Case::new(&[0x36]).wst(&[1, 2, 3, 4, 2]).expect_wst(&[1, 2, 3, 4, 1, 2]);
+    pick2: Case::new(&[0xb6]).wst(&[1, 2, 3, 4, 2]).expect_wst(&[1, 2, 3, 4, 2, 1, 2]);
+    picr2: Case::new(&[0x76]).rst(&[1, 2, 3, 4, 0]).expect_rst(&[1, 2, 3, 4, 3, 4]);
+    pickr2: Case::new(&[0xf6]).rst(&[1, 2, 3, 4, 0]).expect_rst(&[1, 2, 3, 4, 0, 3, 4]);
+
+    put: Case::new(&[0x17]).wst(&[1, 2, 3, 9, 1]).expect_wst(&[1, 9, 3]);
+    putk: Case::new(&[0x97]).wst(&[1, 2, 3, 9, 1]).expect_wst(&[1, 9, 3, 9, 1]);
+    putr: Case::new(&[0x57]).rst(&[1, 2, 3, 9, 0]).expect_rst(&[1, 2, 9]);
+    putkr: Case::new(&[0xd7]).rst(&[1, 2, 3, 9, 0]).expect_rst(&[1, 2, 9, 9, 0]);
+    put2: Case::new(&[0x37]).wst(&[1, 2, 3, 4, 8, 9, 2]).expect_wst(&[8, 9, 3, 4]);
+    putk2: Case::new(&[0xb7]).wst(&[1, 2, 3, 4, 8, 9, 2]).expect_wst(&[8, 9, 3, 4, 8, 9, 2]);
+    putr2: Case::new(&[0x77]).rst(&[1, 2, 3, 4, 8, 9, 0]).expect_rst(&[1, 2, 8, 9]);
+    putkr2: Case::new(&[0xf7]).rst(&[1, 2, 3, 4, 8, 9, 0]).expect_rst(&[1, 2, 8, 9, 8, 9, 0]);
+}
+
+// 3.4: arithmetic
+cases! {
+    adc: Case::new(&[0x18]).wst(&[1, 2]).expect_wst(&[3]).expect_carry(false);
+    adc_carry_in: Case::new(&[0x18]).carry().wst(&[1, 2]).expect_wst(&[4]).expect_carry(false);
+    adc_overflow: Case::new(&[0x18]).wst(&[0xff, 2]).expect_wst(&[1]).expect_carry(true);
+    adc_carry_in_overflow: Case::new(&[0x18]).carry().wst(&[0xff, 0]).expect_wst(&[0]).expect_carry(true);
+    adck: Case::new(&[0x98]).wst(&[1, 2]).expect_wst(&[1, 2, 3]);
+    adcr: Case::new(&[0x58]).rst(&[1, 2]).expect_rst(&[3]);
+    adckr: Case::new(&[0xd8]).carry().rst(&[1, 2]).expect_rst(&[1, 2, 4]);
+    adc2: Case::new(&[0x38]).wst(&[0xff, 0x00, 0x01, 0x00]).expect_wst(&[0x00, 0x01]).expect_carry(false);
+    adc2_overflow: Case::new(&[0x38]).wst(&[0xff, 0xff, 0x02, 0x00]).expect_wst(&[0x01, 0x00]).expect_carry(true);
+    adc2_carry_in_overflow: Case::new(&[0x38]).carry().wst(&[0xff, 0xff, 0x00, 0x00]).expect_wst(&[0x00, 0x00]).expect_carry(true);
+    adck2: Case::new(&[0xb8]).wst(&[0xff, 0x00, 0x01, 0x00]).expect_wst(&[0xff, 0x00, 0x01, 0x00, 0x00, 0x01]);
+    adcr2: Case::new(&[0x78]).rst(&[0xff, 0x00, 0x01, 0x00]).expect_rst(&[0x00, 0x01]);
+    adckr2: Case::new(&[0xf8]).rst(&[0xff, 0x00, 0x01, 0x00]).expect_rst(&[0xff, 0x00, 0x01, 0x00, 0x00, 0x01]);
+
+    // SBC runs `b - a`, where a is on top, and borrows when the carry is clear
+    sbc: Case::new(&[0x19]).carry().wst(&[5, 3]).expect_wst(&[2]).expect_carry(true);
+    sbc_borrow_in: Case::new(&[0x19]).wst(&[5, 3]).expect_wst(&[1]).expect_carry(true);
+    sbc_underflow: Case::new(&[0x19]).carry().wst(&[3, 5]).expect_wst(&[0xfe]).expect_carry(false);
+    sbc_borrow_in_underflow: Case::new(&[0x19]).wst(&[5, 5]).expect_wst(&[0xff]).expect_carry(false);
+    sbck: Case::new(&[0x99]).carry().wst(&[5, 3]).expect_wst(&[5, 3, 2]);
+    sbcr: Case::new(&[0x59]).carry().rst(&[5, 3]).expect_rst(&[2]);
+    sbckr: Case::new(&[0xd9]).rst(&[5, 3]).expect_rst(&[5, 3, 1]);
+    sbc2: Case::new(&[0x39]).carry().wst(&[0x00, 0x01, 0x01, 0x00]).expect_wst(&[0xff, 0x00]).expect_carry(true);
+    sbc2_underflow: Case::new(&[0x39]).carry().wst(&[0x00, 0x00, 0x01, 0x00]).expect_wst(&[0xff, 0xff]).expect_carry(false);
+    sbc2_borrow_in_underflow: Case::new(&[0x39]).wst(&[0x34, 0x12, 0x34, 0x12]).expect_wst(&[0xff, 0xff]).expect_carry(false);
+    sbck2: Case::new(&[0xb9]).carry().wst(&[0x00, 0x01, 0x01, 0x00]).expect_wst(&[0x00, 0x01, 0x01, 0x00, 0xff, 0x00]);
+    sbcr2: Case::new(&[0x79]).rst(&[0x00, 0x01, 0x01, 0x00]).expect_rst(&[0xfe, 0x00]);
+    sbckr2: Case::new(&[0xf9]).rst(&[0x00, 0x01, 0x01, 0x00]).expect_rst(&[0x00, 0x01, 0x01, 0x00, 0xfe, 0x00]);
+
+    mul: Case::new(&[0x1a]).wst(&[3, 4]).expect_wst(&[12]);
+    mul_overflow: Case::new(&[0x1a]).wst(&[0x10, 0x11]).expect_wst(&[0x10]);
+    mulk: Case::new(&[0x9a]).wst(&[3, 4]).expect_wst(&[3, 4, 12]);
+    mulr: Case::new(&[0x5a]).rst(&[3, 4]).expect_rst(&[12]);
+    mulkr: Case::new(&[0xda]).rst(&[3, 4]).expect_rst(&[3, 4, 12]);
+    mul2: Case::new(&[0x3a]).wst(&[0x10, 0x00, 0x10, 0x00]).expect_wst(&[0x00, 0x01]);
+    mulk2: Case::new(&[0xba]).wst(&[0x10, 0x00, 0x10, 0x00]).expect_wst(&[0x10, 0x00, 0x10, 0x00, 0x00, 0x01]);
+    mulr2: Case::new(&[0x7a]).rst(&[0x00, 0x01, 0x00, 0x01]).expect_rst(&[0x00, 0x00]);
+    mulkr2: Case::new(&[0xfa]).rst(&[0x10, 0x00, 0x10, 0x00]).expect_rst(&[0x10, 0x00, 0x10, 0x00, 0x00, 0x01]);
+
+    // DVM runs `b / a` and `b % a`, where a is on top
+    dvm: Case::new(&[0x1b]).wst(&[7, 2]).expect_wst(&[3, 1]);
+    dvmk: Case::new(&[0x9b]).wst(&[7, 2]).expect_wst(&[7, 2, 3, 1]);
+    dvmr: Case::new(&[0x5b]).rst(&[7, 2]).expect_rst(&[3, 1]);
+    dvmkr: Case::new(&[0xdb]).rst(&[7, 2]).expect_rst(&[7, 2, 3, 1]);
+    dvm2: Case::new(&[0x3b]).wst(&[0x00, 0x01, 0x03, 0x00]).expect_wst(&[0x55, 0x00, 0x01, 0x00]);
+    dvmk2: Case::new(&[0xbb]).wst(&[0x00, 0x01, 0x03, 0x00]).expect_wst(&[0x00, 0x01, 0x03, 0x00, 0x55, 0x00, 0x01, 0x00]);
+    dvmr2: Case::new(&[0x7b]).rst(&[0x00, 0x01, 0x03, 0x00]).expect_rst(&[0x55, 0x00, 0x01, 0x00]);
+    dvmkr2: Case::new(&[0xfb]).rst(&[0x00, 0x01, 0x03, 0x00]).expect_rst(&[0x00, 0x01, 0x03, 0x00, 0x55, 0x00, 0x01, 0x00]);
+
+    and: Case::new(&[0x1c]).wst(&[0x0f, 0x3c]).expect_wst(&[0x0c]);
+    andk: Case::new(&[0x9c]).wst(&[0x0f, 0x3c]).expect_wst(&[0x0f, 0x3c, 0x0c]);
+    andr: Case::new(&[0x5c]).rst(&[0x0f, 0x3c]).expect_rst(&[0x0c]);
+    andkr: Case::new(&[0xdc]).rst(&[0x0f, 0x3c]).expect_rst(&[0x0f, 0x3c, 0x0c]);
+    and2: Case::new(&[0x3c]).wst(&[0x0f, 0xf0, 0x3c, 0x3c]).expect_wst(&[0x0c, 0x30]);
+    andk2: Case::new(&[0xbc]).wst(&[0x0f, 0xf0, 0x3c, 0x3c]).expect_wst(&[0x0f, 0xf0, 0x3c, 0x3c, 0x0c, 0x30]);
+    andr2: Case::new(&[0x7c]).rst(&[0x0f, 0xf0, 0x3c, 0x3c]).expect_rst(&[0x0c, 0x30]);
+    andkr2: Case::new(&[0xfc]).rst(&[0x0f, 0xf0, 0x3c, 0x3c]).expect_rst(&[0x0f, 0xf0, 0x3c, 0x3c, 0x0c, 0x30]);
+
+    ior: Case::new(&[0x1d]).wst(&[0x0f, 0x3c]).expect_wst(&[0x3f]);
+    iork: Case::new(&[0x9d]).wst(&[0x0f, 0x3c]).expect_wst(&[0x0f, 0x3c, 0x3f]);
+    iorr: Case::new(&[0x5d]).rst(&[0x0f, 0x3c]).expect_rst(&[0x3f]);
+    iorkr: Case::new(&[0xdd]).rst(&[0x0f, 0x3c]).expect_rst(&[0x0f, 0x3c, 0x3f]);
+    ior2: Case::new(&[0x3d]).wst(&[0x0f, 0xf0, 0x3c, 0x3c]).expect_wst(&[0x3f, 0xfc]);
+    iork2: Case::new(&[0xbd]).wst(&[0x0f, 0xf0, 0x3c, 0x3c]).expect_wst(&[0x0f, 0xf0, 0x3c, 0x3c, 0x3f, 0xfc]);
+    iorr2: Case::new(&[0x7d]).rst(&[0x0f, 0xf0, 0x3c, 0x3c]).expect_rst(&[0x3f, 0xfc]);
+    iorkr2: Case::new(&[0xfd]).rst(&[0x0f, 0xf0, 0x3c, 0x3c]).expect_rst(&[0x0f, 0xf0, 0x3c, 0x3c, 0x3f, 0xfc]);
+
+    xor: Case::new(&[0x1e]).wst(&[0x0f, 0x3c]).expect_wst(&[0x33]);
+    xork: Case::new(&[0x9e]).wst(&[0x0f, 0x3c]).expect_wst(&[0x0f, 0x3c, 0x33]);
+    xorr: Case::new(&[0x5e]).rst(&[0x0f, 0x3c]).expect_rst(&[0x33]);
+    xorkr: Case::new(&[0xde]).rst(&[0x0f, 0x3c]).expect_rst(&[0x0f, 0x3c, 0x33]);
+    xor2: Case::new(&[0x3e]).wst(&[0x0f, 0xf0, 0x3c, 0x3c]).expect_wst(&[0x33, 0xcc]);
+    xork2: Case::new(&[0xbe]).wst(&[0x0f, 0xf0, 0x3c, 0x3c]).expect_wst(&[0x0f, 0xf0, 0x3c, 0x3c, 0x33, 0xcc]);
+    xorr2: Case::new(&[0x7e]).rst(&[0x0f, 0xf0, 0x3c, 0x3c]).expect_rst(&[0x33, 0xcc]);
+    xorkr2: Case::new(&[0xfe]).rst(&[0x0f, 0xf0, 0x3c, 0x3c]).expect_rst(&[0x0f, 0xf0, 0x3c, 0x3c, 0x33, 0xcc]);
+
+    // the high nybble shifts left, then the low nybble shifts right
+    sft: Case::new(&[0x1f]).wst(&[0x0f, 0x21]).expect_wst(&[0x1e]);
+    sft_left_first: Case::new(&[0x1f]).wst(&[0xff, 0x11]).expect_wst(&[0x7f]);
+    sft_whole_width: Case::new(&[0x1f]).wst(&[0xff, 0x80]).expect_wst(&[0x00]);
+    sft_right_whole_width: Case::new(&[0x1f]).wst(&[0xff, 0x0f]).expect_wst(&[0x00]);
+    sftk: Case::new(&[0x9f]).wst(&[0x0f, 0x21]).expect_wst(&[0x0f, 0x21, 0x1e]);
+    sftr: Case::new(&[0x5f]).rst(&[0x0f, 0x21]).expect_rst(&[0x1e]);
+    sftkr: Case::new(&[0xdf]).rst(&[0x0f, 0x21]).expect_rst(&[0x0f, 0x21, 0x1e]);
+    sft2: Case::new(&[0x3f]).wst(&[0xff, 0x00, 0x84]).expect_wst(&[0xf0, 0x0f]);
+    sft2_wide: Case::new(&[0x3f]).wst(&[0xff, 0xff, 0xf1]).expect_wst(&[0x00, 0x40]);
+    sftk2: Case::new(&[0xbf]).wst(&[0xff, 0x00, 0x84]).expect_wst(&[0xff, 0x00, 0x84, 0xf0, 0x0f]);
+    sftr2: Case::new(&[0x7f]).rst(&[0xff, 0x00, 0x84]).expect_rst(&[0xf0, 0x0f]);
+    sftkr2: Case::new(&[0xff]).rst(&[0xff, 0x00, 0x84]).expect_rst(&[0xff, 0x00, 0x84, 0xf0, 0x0f]);
+}
+
+// 3.5 and 3.6: literals and odds and ends
+cases! {
+    nop: Case::new(&[0x00]).wst(&[1]).rst(&[2]);
+    lit: Case::new(&[0x80, 0x12]).expect_wst(&[0x12]);
+    litr: Case::new(&[0xc0, 0x12]).expect_rst(&[0x12]);
+    lit2: Case::new(&[0xa0, 0x12, 0x34]).expect_wst(&[0x34, 0x12]);
+    litr2: Case::new(&[0xe0, 0x12, 0x34]).expect_rst(&[0x34, 0x12]);
+    sec: Case::new(&[0x20]).expect_carry(true);
+    clc: Case::new(&[0x40]).carry().expect_carry(false);
+    ext: Case::new(&[0x60]).expect_wst(&[0x00]);
+}
+
+/// RTI pops the status, then a return address which (like the other jumps) it goes one past
+#[test]
+fn rti() {
+    let mut p = Processor::new(&[0x80, 0x01, 0xc0, 0x33, 0xc0, 0x12, 0x83], Vec::new()).unwrap();
+    for _ in 0..4 {
+        assert_eq!(p.execute_once(), Status::Running)
+    }
+    assert_eq!((p.pc(), p.st()), (0x1234, 0x01));
+    assert!(p.working_stack().is_empty() && p.return_stack().is_empty());
+}
+
+#[test]
+fn jmp_backwards() {
+    let mut p = Processor::new(&[0x80, 0xfe, 0x0a], Vec::new()).unwrap();
+    for _ in 0..2 {
+        assert_eq!(p.execute_once(), Status::Running)
+    }
+    assert_eq!(p.pc(), 0x0300);
+}
+
+/// a subroutine can return by jumping to the address JSR left
+#[test]
+fn jsr_return() {
+    let rom = [
+        0x80, 0x02, 0x0c, // LIT 02 JSR
+        0x00, // nop, to return to
+        0x6a // JMPr2
+    ];
+    let mut p = Processor::new(&rom, Vec::new()).unwrap();
+    for _ in 0..3 {
+        assert_eq!(p.execute_once(), Status::Running)
+    }
+    assert_eq!(p.pc(), 0x0303);
+    assert!(p.return_stack().is_empty());
+}
+
+#[test]
+fn dvm_by_zero() {
+    for instr in [0x1b, 0x3b] {
+        let mut p = Processor::new(&[0xa0, 0x00, 0x01, 0xa0, 0x00, 0x00, instr], Vec::new()).unwrap();
+        p.execute_once();
+        p.execute_once();
+        assert_eq!(p.execute_once(), Status::Faulted { fault: Fault::DivideByZero, pc: 0x0306, instr });
+    }
+}
+
+#[test]
+fn relative_jump_wraps() {
+    let mut p = Processor::new(&[], Vec::new()).unwrap();
+    p.mem_mut().main_mut()[0x0000] = 0x0a; // JMP
+    p.mem_mut().main_mut()[0x01ff] = 0xfe;
+    p.set_registers(0xfe, 0xff, 0, 0x0000);
+    assert_eq!(p.execute_once(), Status::Running);
+    assert_eq!(p.pc(), 0xfffe);
+}
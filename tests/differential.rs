@@ -0,0 +1,221 @@
+//! differential tests: random machines run on both `Processor` and the reference model in
+//! tests/reference, which have to agree after every instruction
+//!
+//! the same machines also run with the block cache and without it, with a device attached, and
+//! have to agree after every block
+//!
+//! a failing case is shrunk by proptest, and printed as the registers, stacks, zero page and a
+//! listing of the code it started with
+
+mod reference;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+use avc2::{disasm, Device, Processor, Status, UndefinedPolicy, WriteResponse};
+use reference::{Machine, Stop, DEVICE_PAGE};
+
+/// the most instructions a case runs for
+const STEPS: usize = 32;
+
+/// the machine a case starts with. everything not given here is 0
+#[derive(Clone)]
+struct Start {
+    pc: u16,
+    st: u8,
+    zero_page: Vec<u8>,
+    /// what's on the working stack, bottom first. the stack pointers start just below it
+    wst: Vec<u8>,
+    rst: Vec<u8>,
+    /// the bytes at the pc
+    code: Vec<u8>
+}
+
+impl Start {
+    fn machine(&self) -> Machine {
+        let mut mem = vec![0; DEVICE_PAGE as usize];
+        mem[0x0000..0x0100].copy_from_slice(&self.zero_page);
+        mem[0x0200 - self.wst.len()..0x0200].iter_mut().rev().zip(&self.wst).for_each(|(m, b)| *m = *b);
+        mem[0x0300 - self.rst.len()..0x0300].iter_mut().rev().zip(&self.rst).for_each(|(m, b)| *m = *b);
+        let pc = self.pc as usize;
+        mem[pc..pc + self.code.len()].copy_from_slice(&self.code);
+        let sp = |stack: &[u8]| 0xff - stack.len() as u8;
+        Machine::new(mem, self.pc, sp(&self.wst), sp(&self.rst), self.st)
+    }
+}
+
+impl fmt::Debug for Start {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "pc {:04x}  st {:02x}", self.pc, self.st)?;
+        writeln!(f, "wst {}", hex(&self.wst))?;
+        writeln!(f, "rst {}", hex(&self.rst))?;
+        let zp: Vec<String> = self.zero_page.iter().enumerate()
+            .filter(|(_, b)| **b != 0)
+            .map(|(i, b)| format!("{:02x}={:02x}", i, b))
+            .collect();
+        writeln!(f, "zero page {}", zp.join(" "))?;
+        write!(f, "{}", disasm::listing(&self.code, self.pc))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    bytes.join(" ")
+}
+
+/// stacks are kept short so failures shrink well. popping past the bottom still happens, and
+/// reads the zeroes below
+fn start() -> impl Strategy<Value = Start> {
+    start_with(any::<u8>())
+}
+/// stacks full of 0xff, so absolute addresses are often on the device page
+fn start_near_devices() -> impl Strategy<Value = Start> {
+    start_with(prop_oneof![any::<u8>(), Just(0xff)])
+}
+fn start_with(stack_byte: impl Strategy<Value = u8> + Clone) -> impl Strategy<Value = Start> {
+    let bytes = |len: std::ops::Range<usize>| prop::collection::vec(any::<u8>(), len);
+    let stack = prop::collection::vec(stack_byte, 0..64);
+    (0u16..DEVICE_PAGE - STEPS as u16, any::<u8>(), bytes(0x100..0x101), stack.clone(), stack, bytes(1..STEPS))
+        .prop_map(|(pc, st, zero_page, wst, rst, code)| Start { pc, st, zero_page, wst, rst, code })
+}
+
+fn processor(model: &Machine) -> Processor {
+    let mut p = Processor::new(&[], Vec::new()).unwrap();
+    p.set_undefined_policy(UndefinedPolicy::Nop);
+    p.mem_mut().main_mut().copy_from_slice(&model.mem);
+    p.set_registers(model.wsp, model.rsp, model.st, model.pc);
+    p
+}
+
+/// run both interpreters side by side until one stops or touches the device page
+fn run(start: &Start) -> Result<(), TestCaseError> {
+    let mut model = start.machine();
+    let mut p = processor(&model);
+
+    for step in 1..=STEPS {
+        let (pc, instr) = (model.pc, p.mem().peek(model.pc));
+        let expected = match model.step() {
+            Ok(()) => None,
+            Err(Stop::Device) => return Ok(()),
+            Err(Stop::Fault(fault)) => Some(fault)
+        };
+        let fault = match p.execute_once() {
+            Status::Running => None,
+            Status::Faulted { fault, .. } => Some(fault),
+            status => return Err(TestCaseError::fail(format!("step {}: unexpected {:?}", step, status)))
+        };
+        let at = format!("step {}, {:02x} at {:04x}", step, instr, pc);
+        prop_assert_eq!(&fault, &expected, "{}: fault", at);
+        if fault.is_some() {
+            return Ok(()) // the model stops half way through the faulting instruction
+        }
+        prop_assert_eq!(
+            (p.pc(), p.wsp(), p.rsp(), p.st()),
+            (model.pc, model.wsp, model.rsp, model.st),
+            "{}: pc, wsp, rsp, st", at
+        );
+        if p.mem().main() != &model.mem[..] {
+            let addr = (0..DEVICE_PAGE as usize).find(|a| p.mem().main()[*a] != model.mem[*a]).unwrap();
+            return Err(TestCaseError::fail(format!("{}: memory at {:04x} is {:02x}, expected {:02x}",
+                at, addr, p.mem().main()[addr], model.mem[addr])))
+        }
+    }
+    Ok(())
+}
+
+/// every device access: the slot, the instruction count, the port and the value written
+type Log = Arc<Mutex<Vec<(usize, u64, u8, Option<u8>)>>>;
+
+/// goes in every slot, standing in for the system device in slot 0. reads depend on the
+/// instruction count, port f halts like the system device's, and there's an interrupt every 16
+/// instructions, so the block cache has to get the timing of all of them right
+struct Probe {
+    slot: usize,
+    ticks: u64,
+    irq: bool,
+    log: Log
+}
+impl Device for Probe {
+    fn write(&mut self, addr: u8, val: u8) -> WriteResponse {
+        self.log.lock().unwrap().push((self.slot, self.ticks, addr, Some(val)));
+        if addr == 0xf { WriteResponse::Shutdown(val) } else { WriteResponse::None }
+    }
+    fn read(&mut self, addr: u8) -> u8 {
+        self.log.lock().unwrap().push((self.slot, self.ticks, addr, None));
+        self.ticks as u8 ^ addr
+    }
+    fn devid(&self) -> u8 {
+        1
+    }
+    fn tick(&mut self) {
+        self.ticks += 1;
+        self.irq |= self.ticks.is_multiple_of(16)
+    }
+    fn irq(&self) -> bool {
+        self.irq
+    }
+    fn ack_irq(&mut self) {
+        self.irq = false
+    }
+}
+
+fn probed(model: &Machine, blocks: bool) -> (Processor, Log) {
+    let mut p = processor(model);
+    let log = Log::default();
+    for slot in 0..16 {
+        p.attach_device(slot, Box::new(Probe { slot, ticks: 0, irq: false, log: log.clone() })).unwrap()
+    }
+    p.set_block_cache(blocks);
+    (p, log)
+}
+
+/// run the block cache beside the plain interpreter, which steps over each block one
+/// instruction at a time
+fn run_blocks(start: &Start) -> Result<(), TestCaseError> {
+    let model = start.machine();
+    let (mut plain, plain_log) = probed(&model, false);
+    let (mut p, log) = probed(&model, true);
+
+    let mut steps = 0;
+    while steps < STEPS {
+        let pc = p.pc();
+        let (status, ran) = p.execute_block();
+        let n = ran + matches!(status, Status::Faulted{..}) as usize;
+        let mut expected = Status::Running;
+        for i in 0..n {
+            expected = plain.execute_once();
+            if i + 1 < n && expected != Status::Running {
+                return Err(TestCaseError::fail(format!("block at {:04x}: {:?} part way through", pc, expected)))
+            }
+        }
+        let at = format!("block at {:04x}, {} instructions", pc, ran);
+        prop_assert_eq!(&status, &expected, "{}: status", at);
+        prop_assert_eq!(
+            (p.pc(), p.wsp(), p.rsp(), p.st()),
+            (plain.pc(), plain.wsp(), plain.rsp(), plain.st()),
+            "{}: pc, wsp, rsp, st", at
+        );
+        prop_assert!(p.mem().main() == plain.mem().main(), "{}: memory", at);
+        prop_assert_eq!(&*log.lock().unwrap(), &*plain_log.lock().unwrap(), "{}: device accesses", at);
+        if status != Status::Running {
+            return Ok(())
+        }
+        steps += n
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(1000))]
+
+    #[test]
+    fn matches_reference(start in start()) {
+        run(&start)?
+    }
+
+    #[test]
+    fn blocks_match_interpreter(start in start_near_devices()) {
+        run_blocks(&start)?
+    }
+}
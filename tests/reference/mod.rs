@@ -0,0 +1,280 @@
+//! a deliberately naive avc2 interpreter, written from specification.md without looking at
+//! `Processor::execute`
+//!
+//! every value is a u16 and each opcode has one arm, whatever its width, so a bug in only one
+//! of the real interpreter's 8-bit and 16-bit arms shows up as a difference. keep mode is done
+//! by putting the stack pointer back after the inputs are read, rather than by picking. there
+//! are no devices: touching the device page stops the model
+
+use avc2::Fault;
+
+pub const DEVICE_PAGE: u16 = 0xff00;
+const WST: u16 = 0x0100;
+const RST: u16 = 0x0200;
+const CARRY: u8 = 0b001;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    Fault(Fault),
+    /// the instruction needs the device page, which the model doesn't have
+    Device
+}
+
+#[derive(Clone)]
+pub struct Machine {
+    /// everything below the device page
+    pub mem: Vec<u8>,
+    pub pc: u16,
+    pub wsp: u8,
+    pub rsp: u8,
+    pub st: u8,
+    /// the stack pointer to go back to once an instruction in keep mode has read its inputs
+    restore: Option<u8>
+}
+
+impl Machine {
+    pub fn new(mem: Vec<u8>, pc: u16, wsp: u8, rsp: u8, st: u8) -> Machine {
+        assert_eq!(mem.len(), DEVICE_PAGE as usize);
+        Machine { mem, pc, wsp, rsp, st, restore: None }
+    }
+
+    /// run one instruction. if it stops, the machine is left half way through it
+    pub fn step(&mut self) -> Result<(), Stop> {
+        let pc = self.pc;
+        let instr = self.read(pc, 1)? as u8;
+        if instr == 0xef { // the debug break, an avc2 extension
+            return Err(Stop::Fault(Fault::DebugBreak))
+        }
+        let k = instr & 0x80 != 0;
+        let r = instr & 0x40 != 0;
+        let w = if instr & 0x20 != 0 { 2 } else { 1 };
+        let op = instr & 0x1f;
+        // the spec leaves keep mode undefined on stack primitives. avc2 ignores it
+        let keeps = k && !matches!(op, 0 | 3..=7);
+        self.restore = if keeps { Some(self.sp(r)) } else { None };
+        let mut next = pc.wrapping_add(1);
+
+        match op {
+            0 => match instr {
+                0x20 => self.st |= CARRY, // SEC
+                0x40 => self.st &= !CARRY, // CLC
+                0x60 => self.push(false, 0, 1), // EXT
+                0x00 => {}
+                _ => { // LIT
+                    let v = self.read(pc.wrapping_add(1), w)?;
+                    self.push(r, v, w);
+                    next = pc.wrapping_add(1 + w)
+                }
+            },
+            3 if instr == 0x83 => { // RTI
+                self.st = self.pop(false, 1) as u8;
+                // avc2 enters interrupts with the address before the next instruction, so
+                // like its other jumps this goes one past the address
+                next = self.pop(true, 2).wrapping_add(1)
+            }
+            3 => { // POP
+                self.pop(r, w);
+            }
+            4 => { // SWP
+                let b = self.pop(r, w);
+                let a = self.pop(r, w);
+                self.push(r, b, w);
+                self.push(r, a, w)
+            }
+            5 => { // ROT
+                let c = self.pop(r, w);
+                let b = self.pop(r, w);
+                let a = self.pop(r, w);
+                self.push(r, b, w);
+                self.push(r, a, w);
+                self.push(r, c, w)
+            }
+            6 => { // DUP
+                let a = self.pop(r, w);
+                self.push(r, a, w);
+                self.push(r, a, w)
+            }
+            7 => { // OVR
+                let b = self.pop(r, w);
+                let a = self.pop(r, w);
+                self.push(r, a, w);
+                self.push(r, b, w);
+                self.push(r, a, w)
+            }
+            8 | 9 => { // EQU and GTH
+                let b = self.pop(r, w);
+                let a = self.pop(r, w);
+                self.inputs_read(r);
+                let result = if op == 8 { a == b } else { signed(a, w) > signed(b, w) };
+                self.push(r, if result { 0xff } else { 0 }, 1)
+            }
+            0xa..=0xc => { // JMP, JNZ and JSR
+                let addr = self.pop(r, w);
+                let jump = op != 0xb || self.pop(r, 1) != 0;
+                self.inputs_read(r);
+                if op == 0xc {
+                    self.push(!r, pc.wrapping_add(1), 2)
+                }
+                if jump {
+                    next = if w == 2 { addr } else { offset(pc, addr) }
+                }
+            }
+            0xd => { // STH
+                let v = self.pop(r, w);
+                self.inputs_read(r);
+                self.push(!r, v, w)
+            }
+            0x10..=0x15 => { // LDZ, STZ, LDR, STR, LDA and STA
+                let addr = match op {
+                    0x10 | 0x11 => self.pop(r, 1),
+                    0x12 | 0x13 => offset(pc, self.pop(r, 1)),
+                    _ => self.pop(r, 2)
+                };
+                if op & 1 == 0 {
+                    self.inputs_read(r);
+                    let v = self.read(addr, w)?;
+                    self.push(r, v, w)
+                }
+                else {
+                    let v = self.pop(r, w);
+                    self.inputs_read(r);
+                    self.write(addr, v, w)?
+                }
+            }
+            0x16 => { // PIC
+                let ofs = self.pop(r, 1) as u8;
+                let v = self.stack_read(r, ofs, w);
+                self.inputs_read(r);
+                self.push(r, v, w)
+            }
+            0x17 => { // PUT
+                let ofs = self.pop(r, 1) as u8;
+                let v = self.pop(r, w);
+                self.stack_write(r, ofs, v, w);
+                self.inputs_read(r)
+            }
+            0x18..=0x1e => {
+                let a = self.pop(r, w) as u32;
+                let b = self.pop(r, w) as u32;
+                self.inputs_read(r);
+                let mask = if w == 2 { 0xffff } else { 0xff };
+                let carry = (self.st & CARRY) as u32;
+                let result = match op {
+                    0x18 => { // ADC
+                        let sum = a + b + carry;
+                        self.set_carry(sum > mask);
+                        sum
+                    }
+                    0x19 => { // SBC
+                        let diff = b as i32 - a as i32 - (1 - carry as i32);
+                        self.set_carry(diff >= 0);
+                        diff as u32
+                    }
+                    0x1a => a * b, // MUL
+                    0x1b => { // DVM
+                        if a == 0 {
+                            return Err(Stop::Fault(Fault::DivideByZero))
+                        }
+                        self.push(r, (b / a) as u16, w);
+                        b % a
+                    }
+                    0x1c => a & b,
+                    0x1d => a | b,
+                    _ => a ^ b
+                };
+                self.push(r, (result & mask) as u16, w)
+            }
+            0x1f => { // SFT
+                let amount = self.pop(r, 1) as u32;
+                let v = self.pop(r, w) as u64;
+                self.inputs_read(r);
+                let mask = if w == 2 { 0xffff } else { 0xff };
+                let v = ((v << (amount >> 4)) & mask) >> (amount & 0xf);
+                self.push(r, v as u16, w)
+            }
+            _ => {} // 1, 2, 0xe and 0xf have nothing in them
+        }
+        self.pc = next;
+        Ok(())
+    }
+
+    /// read a big-endian value of width `w`
+    fn read(&self, addr: u16, w: u16) -> Result<u16, Stop> {
+        let mut v = 0;
+        for i in 0..w {
+            let a = addr.wrapping_add(i);
+            if a >= DEVICE_PAGE {
+                return Err(Stop::Device)
+            }
+            v = v << 8 | self.mem[a as usize] as u16
+        }
+        Ok(v)
+    }
+    fn write(&mut self, addr: u16, v: u16, w: u16) -> Result<(), Stop> {
+        let bytes = v.to_be_bytes();
+        for i in 0..w {
+            let a = addr.wrapping_add(i);
+            if a >= DEVICE_PAGE {
+                return Err(Stop::Device)
+            }
+            self.mem[a as usize] = bytes[(2 - w + i) as usize]
+        }
+        Ok(())
+    }
+
+    fn sp(&self, r: bool) -> u8 {
+        if r { self.rsp } else { self.wsp }
+    }
+    fn set_sp(&mut self, r: bool, sp: u8) {
+        if r { self.rsp = sp } else { self.wsp = sp }
+    }
+    /// the address of the byte `ofs` down from the top of a stack
+    fn stack_addr(&self, r: bool, ofs: u8) -> usize {
+        let base = if r { RST } else { WST };
+        (base + self.sp(r).wrapping_add(1).wrapping_add(ofs) as u16) as usize
+    }
+
+    /// values are pushed a byte at a time, low byte first, so the high byte ends up on top
+    fn push(&mut self, r: bool, v: u16, w: u16) {
+        let bytes = v.to_be_bytes();
+        for i in (0..w).rev() {
+            let addr = self.stack_addr(r, 0xff);
+            self.mem[addr] = bytes[(2 - w + i) as usize];
+            self.set_sp(r, self.sp(r).wrapping_sub(1))
+        }
+    }
+    fn pop(&mut self, r: bool, w: u16) -> u16 {
+        let v = self.stack_read(r, 0, w);
+        self.set_sp(r, self.sp(r).wrapping_add(w as u8));
+        v
+    }
+    fn stack_read(&self, r: bool, ofs: u8, w: u16) -> u16 {
+        (0..w).fold(0, |v, i| v << 8 | self.mem[self.stack_addr(r, ofs.wrapping_add(i as u8))] as u16)
+    }
+    fn stack_write(&mut self, r: bool, ofs: u8, v: u16, w: u16) {
+        let bytes = v.to_be_bytes();
+        for i in 0..w {
+            let addr = self.stack_addr(r, ofs.wrapping_add(i as u8));
+            self.mem[addr] = bytes[(2 - w + i) as usize]
+        }
+    }
+    /// in keep mode, leave the inputs where they were
+    fn inputs_read(&mut self, r: bool) {
+        if let Some(sp) = self.restore.take() {
+            self.set_sp(r, sp)
+        }
+    }
+
+    fn set_carry(&mut self, carry: bool) {
+        if carry { self.st |= CARRY } else { self.st &= !CARRY }
+    }
+}
+
+fn signed(v: u16, w: u16) -> i16 {
+    if w == 2 { v as i16 } else { v as u8 as i8 as i16 }
+}
+
+/// a relative address: the signed byte `ofs` added to `pc`
+fn offset(pc: u16, ofs: u16) -> u16 {
+    pc.wrapping_add(ofs as u8 as i8 as u16)
+}